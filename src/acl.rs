@@ -0,0 +1,283 @@
+use libp2p::{PeerId, Swarm};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::{error, fmt, fs, io};
+use tracing::{info, warn};
+
+use crate::VpnBehaviour;
+use crate::config::acl::{Acl as Config, Mode};
+
+/// Lists of allowed and denied peers.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lists {
+    #[serde(default)]
+    pub allow: BTreeSet<PeerId>,
+    #[serde(default)]
+    pub deny: BTreeSet<PeerId>,
+}
+
+/// Error changing the access-control lists
+#[derive(Debug)]
+pub enum Error {
+    /// The file of the lists could not be read or written.
+    Io(String, io::Error),
+    /// The file of the lists is not valid YAML.
+    Parse(String, serde_yaml::Error),
+    /// The peer is listed in the configuration, which the control-API does not change.
+    Configured(PeerId),
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            Error::Parse(_, err) => Some(err),
+            Error::Configured(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "Error accessing {path}: {e}"),
+            Error::Parse(path, e) => write!(f, "Error parsing {path}: {e}"),
+            Error::Configured(peer_id) => write!(f, "{peer_id} is listed in the configuration"),
+        }
+    }
+}
+
+/// Admits peers by the lists of the configuration and the ones changed through the
+/// control-API, which are saved to `acl.file`. Denied peers are blocked, in allow-mode only the
+/// allowed ones are admitted to the VPN.
+pub struct Acl {
+    config: Config,
+    /// The peers allowed and denied through the control-API.
+    saved: Lists,
+    /// The lists as last applied to the swarm.
+    applied: Lists,
+}
+
+impl Acl {
+    /// Load the lists saved to `config.file`, if any.
+    pub fn load(config: Config) -> Result<Self, Error> {
+        let saved = read(Path::new(&config.file))?;
+
+        Ok(Self {
+            config,
+            saved,
+            applied: Lists::default(),
+        })
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.config.mode
+    }
+
+    /// The effective lists, of the configuration and the control-API.
+    pub fn lists(&self) -> Lists {
+        Lists {
+            allow: self
+                .config
+                .allow
+                .iter()
+                .chain(&self.saved.allow)
+                .copied()
+                .collect(),
+            deny: self
+                .config
+                .deny
+                .iter()
+                .chain(&self.saved.deny)
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Apply a reloaded configuration, reading the lists again if `file` changed.
+    pub fn set_config(&mut self, config: Config, swarm: &mut Swarm<VpnBehaviour>) {
+        if config.file != self.config.file {
+            match read(Path::new(&config.file)) {
+                Ok(saved) => self.saved = saved,
+                Err(e) => warn!("Keeping the access-control lists, {e}"),
+            }
+        }
+        self.config = config;
+        self.apply(swarm);
+    }
+
+    /// Deny `peer_id`, closing its connections.
+    pub fn deny(&mut self, peer_id: PeerId, swarm: &mut Swarm<VpnBehaviour>) -> Result<(), Error> {
+        self.change(swarm, |saved| saved.deny.insert(peer_id))
+    }
+
+    /// Stop denying `peer_id`, unless the configuration does.
+    pub fn undeny(
+        &mut self,
+        peer_id: PeerId,
+        swarm: &mut Swarm<VpnBehaviour>,
+    ) -> Result<(), Error> {
+        if self.config.deny.contains(&peer_id) {
+            return Err(Error::Configured(peer_id));
+        }
+        self.change(swarm, |saved| saved.deny.remove(&peer_id))
+    }
+
+    pub fn allow(&mut self, peer_id: PeerId, swarm: &mut Swarm<VpnBehaviour>) -> Result<(), Error> {
+        self.change(swarm, |saved| saved.allow.insert(peer_id))
+    }
+
+    /// Stop allowing `peer_id`, unless the configuration does. In allow-mode its tunnels are
+    /// closed.
+    pub fn disallow(
+        &mut self,
+        peer_id: PeerId,
+        swarm: &mut Swarm<VpnBehaviour>,
+    ) -> Result<(), Error> {
+        if self.config.allow.contains(&peer_id) {
+            return Err(Error::Configured(peer_id));
+        }
+        self.change(swarm, |saved| saved.allow.remove(&peer_id))
+    }
+
+    /// Change the saved lists and apply them.
+    fn change(
+        &mut self,
+        swarm: &mut Swarm<VpnBehaviour>,
+        change: impl FnOnce(&mut Lists) -> bool,
+    ) -> Result<(), Error> {
+        if self.save(change)? {
+            self.apply(swarm);
+        }
+        Ok(())
+    }
+
+    /// Change the saved lists, writing them to the file before they take effect. Whether they
+    /// changed.
+    fn save(&mut self, change: impl FnOnce(&mut Lists) -> bool) -> Result<bool, Error> {
+        let mut saved = self.saved.clone();
+        if !change(&mut saved) {
+            return Ok(false);
+        }
+
+        write(Path::new(&self.config.file), &saved)?;
+        self.saved = saved;
+        Ok(true)
+    }
+
+    /// Bring the swarm in line with the lists: block the denied peers and, in allow-mode, admit
+    /// only the allowed ones to the VPN. Other peers still connect, e.g. for the DHT or relaying.
+    pub fn apply(&mut self, swarm: &mut Swarm<VpnBehaviour>) {
+        let lists = self.lists();
+        let behaviour = swarm.behaviour_mut();
+
+        for peer_id in lists.deny.difference(&self.applied.deny) {
+            info!("Denying {}", peer_id);
+            behaviour.blocked_peers.block_peer(*peer_id);
+        }
+        for peer_id in self.applied.deny.difference(&lists.deny) {
+            info!("No longer denying {}", peer_id);
+            behaviour.blocked_peers.unblock_peer(*peer_id);
+        }
+
+        behaviour.vpn.set_allowed(match self.config.mode {
+            Mode::Allow => Some(lists.allow.iter().copied().collect()),
+            Mode::Deny => None,
+        });
+
+        self.applied = lists;
+    }
+}
+
+fn read(path: &Path) -> Result<Lists, Error> {
+    let name = path.display().to_string();
+    match fs::read_to_string(path) {
+        Ok(content) => serde_yaml::from_str(&content).map_err(|e| Error::Parse(name, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Lists::default()),
+        Err(e) => Err(Error::Io(name, e)),
+    }
+}
+
+/// Replace the file, so a crash leaves either the old or the new lists.
+fn write(path: &Path, lists: &Lists) -> Result<(), Error> {
+    let name = path.display().to_string();
+    let content = serde_yaml::to_string(lists).map_err(|e| Error::Parse(name.clone(), e))?;
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, content)
+        .and_then(|()| fs::rename(&temporary, path))
+        .map_err(|e| Error::Io(name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{peer_id, temporary};
+
+    #[test]
+    fn lists_roundtrip() {
+        let file = temporary("acl-roundtrip.yaml");
+        let lists = Lists {
+            allow: BTreeSet::from([peer_id(), peer_id()]),
+            deny: BTreeSet::from([peer_id()]),
+        };
+
+        write(&file, &lists).unwrap();
+        assert_eq!(read(&file).unwrap(), lists);
+
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn missing_file_has_empty_lists() {
+        let file = temporary("acl-missing.yaml");
+        assert_eq!(read(&file).unwrap(), Lists::default());
+    }
+
+    #[test]
+    fn lists_combine_configuration_and_saved() {
+        let file = temporary("acl-combine.yaml");
+        let (configured, saved) = (peer_id(), peer_id());
+        let mut acl = Acl::load(Config {
+            allow: vec![configured],
+            deny: vec![configured],
+            file: file.display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        assert!(acl.save(|lists| lists.allow.insert(saved)).unwrap());
+        assert!(acl.save(|lists| lists.deny.insert(saved)).unwrap());
+        assert_eq!(acl.lists().allow, BTreeSet::from([configured, saved]));
+        assert_eq!(acl.lists().deny, BTreeSet::from([configured, saved]));
+
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn changes_are_saved() {
+        let file = temporary("acl-saved.yaml");
+        let config = Config {
+            file: file.display().to_string(),
+            ..Config::default()
+        };
+        let peer = peer_id();
+
+        let mut acl = Acl::load(config.clone()).unwrap();
+        assert!(acl.save(|lists| lists.allow.insert(peer)).unwrap());
+        // An unchanged list is not written again.
+        assert!(!acl.save(|lists| lists.allow.insert(peer)).unwrap());
+        assert_eq!(
+            Acl::load(config.clone()).unwrap().lists().allow,
+            BTreeSet::from([peer])
+        );
+
+        assert!(acl.save(|lists| lists.allow.remove(&peer)).unwrap());
+        assert!(Acl::load(config).unwrap().lists().allow.is_empty());
+
+        fs::remove_file(&file).unwrap();
+    }
+}
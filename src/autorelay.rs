@@ -0,0 +1,160 @@
+use libp2p::{
+    Multiaddr, PeerId, StreamProtocol, Swarm, autonat, core::transport::ListenerId,
+    multiaddr::Protocol, relay,
+};
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::VpnBehaviour;
+use crate::config::relay::RelayClient as Config;
+
+/// Reserves slots on relays among the connected peers while AutoNAT finds this node
+/// unreachable, so other nodes can reach it on the relays' `/p2p-circuit` addresses.
+pub struct AutoRelay {
+    config: Config,
+    enabled: bool,
+    /// Whether AutoNAT found this node unreachable.
+    private: bool,
+    /// Connected peers offering the relay-protocol, with an address to reserve on.
+    candidates: HashMap<PeerId, Multiaddr>,
+    /// Addresses of the peers we dialed.
+    dialed: HashMap<PeerId, Multiaddr>,
+    /// Relays we reserved a slot on, by the listener of the reservation.
+    reservations: HashMap<PeerId, ListenerId>,
+    /// Relays whose reservation failed, not tried again before the given time.
+    backoff: HashMap<PeerId, Instant>,
+}
+
+impl AutoRelay {
+    pub fn new(enabled: bool, config: Config) -> Self {
+        Self {
+            config,
+            enabled,
+            private: false,
+            candidates: HashMap::new(),
+            dialed: HashMap::new(),
+            reservations: HashMap::new(),
+            backoff: HashMap::new(),
+        }
+    }
+
+    /// Apply a reloaded configuration, the reservations are released unless `enabled`.
+    pub fn set_config(&mut self, enabled: bool, config: Config) {
+        self.config = config;
+        self.enabled = enabled;
+    }
+
+    pub fn on_nat_status(&mut self, status: &autonat::NatStatus) {
+        self.private = matches!(status, autonat::NatStatus::Private);
+    }
+
+    /// A connection to `peer_id` was established, dialed on `address` if we dialed it.
+    pub fn on_connected(&mut self, peer_id: PeerId, address: Option<&Multiaddr>) {
+        if let Some(address) = address {
+            let address: Multiaddr = address
+                .iter()
+                .filter(|protocol| !matches!(protocol, Protocol::P2p(_)))
+                .collect();
+            if !address
+                .iter()
+                .any(|protocol| protocol == Protocol::P2pCircuit)
+            {
+                self.dialed.insert(peer_id, address);
+            }
+        }
+    }
+
+    /// `peer_id` identified itself, listening on `listen_addrs` and supporting `protocols`.
+    /// Relays are reserved on the address we dialed them on, if any.
+    pub fn on_identified(
+        &mut self,
+        peer_id: PeerId,
+        listen_addrs: &[Multiaddr],
+        protocols: &[StreamProtocol],
+    ) {
+        if !protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+            return;
+        }
+
+        let address = self
+            .dialed
+            .get(&peer_id)
+            .or_else(|| listen_addrs.first())
+            .cloned();
+        if let Some(address) = address {
+            debug!("Peer {} offers relaying on {}", peer_id, address);
+            self.candidates.insert(peer_id, address);
+        }
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        self.candidates.remove(peer_id);
+        self.dialed.remove(peer_id);
+        if self.reservations.remove(peer_id).is_some() {
+            info!("Relay {} disconnected, reserving on another one", peer_id);
+        }
+    }
+
+    /// The listener of a reservation closed, the relay refused or dropped it.
+    pub fn on_listener_closed(&mut self, listener_id: ListenerId) {
+        let relay = self
+            .reservations
+            .iter()
+            .find(|(_, id)| **id == listener_id)
+            .map(|(relay, _)| *relay);
+
+        if let Some(relay) = relay {
+            warn!("Reservation on relay {} ended", relay);
+            self.reservations.remove(&relay);
+            self.backoff
+                .insert(relay, Instant::now() + self.config.retry_interval);
+        }
+    }
+
+    /// Reserve on further relays while the node is unreachable, release the reservations once
+    /// it is reachable.
+    pub fn maintain(&mut self, swarm: &mut Swarm<VpnBehaviour>) {
+        if !self.enabled || !self.private {
+            for (relay, listener_id) in self.reservations.drain() {
+                debug!("Releasing reservation on {}", relay);
+                swarm.remove_listener(listener_id);
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        self.backoff.retain(|_, until| *until > now);
+
+        let candidates: Vec<(PeerId, Multiaddr)> = self
+            .candidates
+            .iter()
+            .filter(|(relay, _)| {
+                !self.reservations.contains_key(relay) && !self.backoff.contains_key(relay)
+            })
+            .map(|(relay, address)| (*relay, address.clone()))
+            .take(
+                self.config
+                    .max_reservations
+                    .saturating_sub(self.reservations.len()),
+            )
+            .collect();
+
+        for (relay, address) in candidates {
+            let circuit = address
+                .with(Protocol::P2p(relay))
+                .with(Protocol::P2pCircuit);
+
+            match swarm.listen_on(circuit.clone()) {
+                Ok(listener_id) => {
+                    info!("Reserving on relay {}", circuit);
+                    self.reservations.insert(relay, listener_id);
+                }
+                Err(e) => {
+                    warn!("Error reserving on relay {}, {e}", circuit);
+                    self.backoff.insert(relay, now + self.config.retry_interval);
+                }
+            }
+        }
+    }
+}
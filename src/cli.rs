@@ -1,9 +1,15 @@
+use crate::config::identity::Encoding;
 use clap::{Parser, Subcommand};
-use std::net::IpAddr;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(name = "vpnr CLI arguments")]
 pub struct CliArgs {
+    /// Configuration-file in YAML or JSON, overridden by VPNR_* environment and arguments
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -15,8 +21,22 @@ pub enum Commands {
         keyfile: Option<String>,
     },
     Start {
+        /// The overlay IP-address assigned to the network-interface, derived from the PeerId
+        /// if unset
         #[clap(long)]
         ip_addr: Option<IpAddr>,
+        /// The prefix-length of the overlay network, with an explicit IP-address
+        #[clap(long)]
+        prefix_len: Option<u8>,
+        /// The overlay network derived IP-addresses are assigned from
+        #[clap(long)]
+        network_prefix: Option<IpNet>,
+        /// The MTU of the network-interface
+        #[clap(long)]
+        mtu: Option<u16>,
+        /// A subnet reachable through this node, advertised to the peers
+        #[clap(long = "route")]
+        routes: Vec<IpNet>,
         /// The network-interface to use
         #[clap(long)]
         interface_name: Option<String>,
@@ -25,8 +45,27 @@ pub enum Commands {
         #[clap(long)]
         listen_port: Option<u16>,
         /// The file containing the private-key
-        #[clap(long, default_value = "vpnr_ed25519")]
+        #[clap(long)]
         keyfile: Option<String>,
+        /// Only accept peers holding the pre-shared key, on QUIC too. Defaults to whether the
+        /// keyfile exists
+        #[clap(long)]
+        private_network: Option<bool>,
+        /// The file containing the node's identity, created on first start
+        #[clap(long)]
+        identity_file: Option<String>,
+        /// The encoding of the identity file
+        #[clap(long, value_enum)]
+        identity_encoding: Option<Encoding>,
+        /// The Unix-socket serving the control-API
+        #[clap(long)]
+        control_socket: Option<String>,
+        /// Serve the control-API
+        #[clap(long)]
+        enable_control_api: Option<bool>,
+        /// The address serving the Prometheus-metrics
+        #[clap(long)]
+        metrics_addr: Option<SocketAddr>,
         /// Enable libp2p::identify
         #[clap(long)]
         enable_identify: Option<bool>,
@@ -48,5 +87,51 @@ pub enum Commands {
         /// Enable libp2p::autonat
         #[clap(long)]
         enable_autonat: Option<bool>,
+        /// Enable libp2p::gossipsub, announcing this node to the network
+        #[clap(long)]
+        enable_gossipsub: Option<bool>,
+        /// Enable libp2p::rendezvous, registering at and discovering through rendezvous-points
+        #[clap(long)]
+        enable_rendezvous: Option<bool>,
+        /// Enable libp2p::rendezvous server, acting as rendezvous-point
+        #[clap(long)]
+        enable_rendezvous_server: Option<bool>,
+        /// Reserve slots on relays while AutoNAT finds this node unreachable
+        #[clap(long)]
+        enable_auto_relay: Option<bool>,
+        /// Enable libp2p::ping, measuring the latency of the connections
+        #[clap(long)]
+        enable_ping: Option<bool>,
+    },
+    /// Show the identity, network and reachability of the running node
+    Status {
+        /// The Unix-socket the node serves the control-API on
+        #[clap(long)]
+        control_socket: Option<String>,
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// List the peers connected to the running node
+    Peers {
+        /// The Unix-socket the node serves the control-API on
+        #[clap(long)]
+        control_socket: Option<String>,
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Ping a peer through the overlay, by its PeerId or overlay IP-address
+    Ping {
+        target: String,
+        /// The number of echo-requests to send
+        #[clap(long, short, default_value_t = 4)]
+        count: u32,
+        /// The Unix-socket the node serves the control-API on
+        #[clap(long)]
+        control_socket: Option<String>,
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
     },
 }
@@ -0,0 +1,221 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::error::Error;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::control::{self, Peer, PingReply, Request, Response, Status};
+
+/// Delay between the echo-requests of `ping`.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connection to the control-API of a running node.
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    id: u64,
+}
+
+impl Client {
+    pub async fn connect(path: &str) -> Result<Self, Box<dyn Error>> {
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| format!("Error connecting to {path}, is the node running? {e}"))?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            id: 0,
+        })
+    }
+
+    /// Call `method` and wait for its result.
+    pub async fn call<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, Box<dyn Error>> {
+        self.id += 1;
+        let request = Request {
+            jsonrpc: control::VERSION.to_string(),
+            id: Some(Value::from(self.id)),
+            method: method.to_string(),
+            params,
+        };
+
+        let mut data = serde_json::to_vec(&request)?;
+        data.push(b'\n');
+        self.writer.write_all(&data).await?;
+
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .ok_or("The node closed the control-connection")?;
+        let response: Response = serde_json::from_str(&line)?;
+
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(error.into()),
+            (Some(result), None) => Ok(serde_json::from_value(result)?),
+            (None, None) => Ok(serde_json::from_value(Value::Null)?),
+        }
+    }
+}
+
+pub async fn status(client: &mut Client, json: bool) -> Result<ExitCode, Box<dyn Error>> {
+    if json {
+        let status: Value = client.call("status", Value::Null).await?;
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let status: Status = client.call("status", Value::Null).await?;
+    let overlay: Vec<String> = status.overlay.iter().map(|a| a.to_string()).collect();
+    println!("Peer-id:     {}", status.peer_id);
+    println!("Network-id:  {}", status.network_id);
+    println!(
+        "Network:     {}",
+        match &status.fingerprint {
+            Some(fingerprint) => format!("private, pre-shared key {fingerprint}"),
+            None => String::from("public"),
+        }
+    );
+    println!("Overlay:     {}", overlay.join(", "));
+    println!(
+        "NAT-status:  {}",
+        match &status.public_address {
+            Some(address) => format!("{} on {}", status.nat_status, address),
+            None => status.nat_status.clone(),
+        }
+    );
+    println!(
+        "Peers:       {} connected, {} members announced",
+        status.connected_peers, status.members
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn peers(client: &mut Client, json: bool) -> Result<ExitCode, Box<dyn Error>> {
+    if json {
+        let peers: Value = client.call("peers", Value::Null).await?;
+        println!("{}", serde_json::to_string_pretty(&peers)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let peers: Vec<Peer> = client.call("peers", Value::Null).await?;
+    println!(
+        "{:<52}  {:<16}  {:<16}  {:>9}  {:>10}  {:>10}  NAME",
+        "PEER", "OVERLAY", "TYPE", "RTT", "RX", "TX"
+    );
+    for peer in peers {
+        let overlay: Vec<String> = peer.addresses.iter().map(|a| a.to_string()).collect();
+        let mut kinds: Vec<String> = peer
+            .connections
+            .iter()
+            .map(|connection| connection.kind.to_string())
+            .collect();
+        kinds.sort();
+        kinds.dedup();
+        let rtt = peer
+            .connections
+            .iter()
+            .filter_map(|connection| connection.rtt)
+            .min();
+
+        println!(
+            "{:<52}  {:<16}  {:<16}  {:>9}  {:>10}  {:>10}  {}",
+            peer.peer_id,
+            overlay.join(","),
+            kinds.join(","),
+            rtt.map(milliseconds).unwrap_or_else(|| String::from("-")),
+            bytes(peer.traffic.rx_bytes),
+            bytes(peer.traffic.tx_bytes),
+            peer.name.unwrap_or_default(),
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Send `count` echo-requests to `target` through the overlay, succeeding if any is answered.
+pub async fn ping(
+    client: &mut Client,
+    target: &str,
+    count: u32,
+    json: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut replies = vec![];
+    let mut errors = vec![];
+
+    for n in 0..count {
+        if n > 0 {
+            tokio::time::sleep(PING_INTERVAL).await;
+        }
+
+        match client
+            .call::<PingReply>("ping", json!({ "target": target }))
+            .await
+        {
+            Ok(reply) => {
+                if !json {
+                    println!(
+                        "Reply from {} ({}): seq={} time={}",
+                        reply.address,
+                        reply.peer_id,
+                        reply.sequence,
+                        milliseconds(reply.rtt)
+                    );
+                }
+                replies.push(reply);
+            }
+            Err(e) => {
+                if !json {
+                    println!("{e}");
+                }
+                errors.push(e.to_string());
+            }
+        }
+    }
+
+    if json {
+        let summary = json!({
+            "target": target,
+            "transmitted": count,
+            "received": replies.len(),
+            "replies": replies,
+            "errors": errors,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!(
+            "--- {}: {} sent, {} received, {}% loss",
+            target,
+            count,
+            replies.len(),
+            (count as usize - replies.len()) * 100 / count.max(1) as usize
+        );
+    }
+
+    Ok(match replies.is_empty() {
+        true => ExitCode::FAILURE,
+        false => ExitCode::SUCCESS,
+    })
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1048576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        1048576..1073741824 => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+        _ => format!("{:.1} GiB", bytes as f64 / 1073741824.0),
+    }
+}
@@ -0,0 +1,34 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Which peers are admitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Every peer but the denied ones.
+    #[default]
+    Deny,
+    /// Only the allowed peers, denied ones neither.
+    Allow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Acl {
+    pub mode: Mode,
+    pub allow: Vec<PeerId>,
+    pub deny: Vec<PeerId>,
+    /// Keeps the peers allowed and denied through the control-API across restarts.
+    pub file: String,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Acl {
+            mode: Mode::default(),
+            allow: vec![],
+            deny: vec![],
+            file: String::from("vpnr_acl.yaml"),
+        }
+    }
+}
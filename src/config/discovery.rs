@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Discovery {
     pub dht: bool,
     pub mdns: bool,
@@ -8,4 +9,9 @@ pub struct Discovery {
     pub dcutr: bool,
     pub autonat: bool,
     pub identify: bool,
+    pub gossipsub: bool,
+    pub rendezvous: bool,
+    pub rendezvous_server: bool,
+    pub auto_relay: bool,
+    pub ping: bool,
 }
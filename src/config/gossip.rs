@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gossip {
+    /// Interval between announcements, members not heard of for three intervals are dropped.
+    pub interval: Duration,
+    /// Human-readable name of this node, announced along its addresses.
+    pub name: Option<String>,
+}
+
+impl Default for Gossip {
+    fn default() -> Self {
+        Gossip {
+            interval: Duration::from_secs(60),
+            name: None,
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Encoding of the identity key file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// libp2p's protobuf key-encoding
+    #[default]
+    Protobuf,
+    /// PKCS#8, PEM-armored, ed25519 keys only
+    Pem,
+}
@@ -0,0 +1,47 @@
+use libp2p::kad;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Whether the node answers DHT-queries of other nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Server once an external address is confirmed, client before.
+    #[default]
+    Auto,
+    Client,
+    Server,
+}
+
+impl From<Mode> for Option<kad::Mode> {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Auto => None,
+            Mode::Client => Some(kad::Mode::Client),
+            Mode::Server => Some(kad::Mode::Server),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Kademlia {
+    pub mode: Mode,
+    /// Interval between bootstraps, the first one follows the first peer in the routing-table.
+    pub bootstrap_interval: Duration,
+    /// Lifetime of the published records.
+    pub record_ttl: Duration,
+    /// Interval between publications of the records, shorter than their lifetime.
+    pub republish_interval: Duration,
+}
+
+impl Default for Kademlia {
+    fn default() -> Self {
+        Kademlia {
+            mode: Mode::default(),
+            bootstrap_interval: Duration::from_secs(5 * 60),
+            record_ttl: Duration::from_secs(60 * 60),
+            republish_interval: Duration::from_secs(20 * 60),
+        }
+    }
+}
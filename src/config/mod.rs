@@ -1,46 +1,116 @@
 use crate::cli::{CliArgs, Commands};
+use crate::network::NetworkKey;
+use acl::Acl;
 use autonat::Autonat;
 use connection_limits::ConnectionLimits;
 use discovery::Discovery;
-use libp2p::Multiaddr;
+use gossip::Gossip;
+use identity::Encoding;
+use ipnet::IpNet;
+use kademlia::Kademlia;
+use libp2p::{Multiaddr, PeerId};
+use peer::Peer;
+use ping::Ping;
+use rekey::Rekey;
+use relay::{Relay, RelayClient};
+use rendezvous::Rendezvous;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use source::Error;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 use std::vec::Vec;
 
+pub mod acl;
 pub mod autonat;
 pub mod connection_limits;
 pub mod discovery;
+pub mod gossip;
+pub mod identity;
+pub mod kademlia;
+pub mod peer;
+pub mod ping;
+pub mod rekey;
+pub mod relay;
+pub mod rendezvous;
+pub mod source;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub ip_addr: IpAddr,
+    pub ip_addr: Option<IpAddr>,
+    pub prefix_len: u8,
+    pub network_prefix: IpNet,
+    pub mtu: u16,
+    pub routes: Vec<IpNet>,
     pub listen_addr: IpAddr,
     pub listen_port: u16,
     pub interface_name: String,
     pub keyfile: String,
+    pub private_network: Option<bool>,
+    /// Scopes the DHT- and gossip-protocols, derived from the pre-shared key if unset.
+    pub network_id: Option<String>,
+    pub rekey: Rekey,
+    pub identity_file: String,
+    pub identity_encoding: Encoding,
+    /// The Unix-socket serving the control-API, `None` disables it.
+    pub control_socket: Option<String>,
+    /// The address serving the Prometheus-metrics on `/metrics`, `None` disables it.
+    pub metrics_addr: Option<SocketAddr>,
     pub discovery: Discovery,
+    pub kademlia: Kademlia,
+    pub gossip: Gossip,
+    pub rendezvous: Rendezvous,
+    pub ping: Ping,
     pub connection_limits: ConnectionLimits,
     pub memory_limit: usize,
     pub enable_relay: bool,
+    pub relay: Relay,
+    pub relay_client: RelayClient,
     pub bootstrap: Vec<Multiaddr>,
+    pub peers: Vec<Peer>,
+    pub acl: Acl,
     pub autonat: Autonat,
+    /// Deadline for leaving the network on SIGINT or SIGTERM.
+    pub shutdown_timeout: Duration,
 }
 
 impl Default for Config {
     fn default() -> Self {
         let mut config = Config {
-            ip_addr: "10.0.0.1".parse().unwrap(),
+            ip_addr: None,
+            prefix_len: 24,
+            network_prefix: "10.0.0.0/24".parse().unwrap(),
+            mtu: 1420,
+            routes: vec![],
             listen_addr: "0.0.0.0".parse().unwrap(),
             listen_port: 0,
             interface_name: String::from("vpnr0"),
             keyfile: String::from("vpnr_ed25519"),
+            private_network: None,
+            network_id: None,
+            rekey: Rekey::default(),
+            identity_file: String::from("vpnr_identity"),
+            identity_encoding: Encoding::default(),
+            control_socket: Some(String::from("vpnr.sock")),
+            metrics_addr: None,
             discovery: Discovery::default(),
+            kademlia: Kademlia::default(),
+            gossip: Gossip::default(),
+            rendezvous: Rendezvous::default(),
+            ping: Ping::default(),
             connection_limits: ConnectionLimits::default(),
             memory_limit: 128,
             enable_relay: false,
+            relay: Relay::default(),
+            relay_client: RelayClient::default(),
             bootstrap: vec![],
+            peers: vec![],
+            acl: Acl::default(),
             autonat: Autonat::default(),
+            shutdown_timeout: Duration::from_secs(10),
         };
 
         for addr in [
@@ -66,9 +136,76 @@ impl Default for Config {
     }
 }
 
-impl From<CliArgs> for Config {
-    fn from(args: CliArgs) -> Config {
-        let mut config = Config::default();
+impl Config {
+    /// Layer the configuration: defaults, then the `--config` file, then `VPNR_*`
+    /// environment-variables, then CLI-arguments.
+    pub fn load(args: CliArgs) -> Result<Config, Error> {
+        let mut value = serde_yaml::to_value(Config::default())
+            .map_err(|e| Error::Invalid(String::from("."), e.to_string()))?;
+
+        if let Some(path) = &args.config {
+            source::merge(&mut value, source::read_file(path)?);
+        }
+
+        // Values of environment-variables are parsed as YAML, unless the key takes a string.
+        let variables = source::read_env(std::env::vars(), &value);
+        let mut strings = HashSet::new();
+        let config: Config = loop {
+            let mut value = value.clone();
+            source::merge(&mut value, source::layer(&variables, &strings));
+
+            match serde_path_to_error::deserialize(value) {
+                Ok(config) => break config,
+                Err(e) => {
+                    let key = e.path().to_string();
+                    match variables.iter().find(|variable| variable.covers(&key)) {
+                        Some(variable) if strings.insert(variable.name.clone()) => continue,
+                        Some(variable) => {
+                            return Err(Error::Env(variable.name.clone(), e.inner().to_string()));
+                        }
+                        None => return Err(Error::Invalid(key, e.inner().to_string())),
+                    }
+                }
+            }
+        };
+
+        Ok(config.with_args(args))
+    }
+
+    /// The overlay-address, `ip_addr` if set, otherwise derived from `peer_id` within
+    /// `network_prefix`.
+    pub fn overlay_address(&self, peer_id: &PeerId) -> Option<IpAddr> {
+        match self.ip_addr {
+            Some(ip_addr) => Some(ip_addr),
+            _ => crate::vpn::address::derive(peer_id, &self.network_prefix, 0),
+        }
+    }
+
+    /// The prefix-length of the interface, derived addresses use the one of `network_prefix`.
+    pub fn overlay_prefix_len(&self) -> u8 {
+        match self.ip_addr {
+            Some(_) => self.prefix_len,
+            _ => self.network_prefix.prefix_len(),
+        }
+    }
+
+    /// Whether only holders of the pre-shared key may connect, by default if the keyfile exists.
+    pub fn is_private_network(&self) -> bool {
+        self.private_network
+            .unwrap_or_else(|| Path::new(&self.keyfile).exists())
+    }
+
+    /// The network-id, `network_id` if set, otherwise derived from the pre-shared key.
+    pub fn network_id(&self, network: Option<&NetworkKey>) -> String {
+        match (&self.network_id, network) {
+            (Some(network_id), _) => network_id.clone(),
+            (None, Some(network)) => network.network_id(),
+            (None, None) => String::from("public"),
+        }
+    }
+
+    fn with_args(self, args: CliArgs) -> Config {
+        let mut config = self;
 
         match args.command {
             Commands::GenerateKey { keyfile } => {
@@ -77,12 +214,30 @@ impl From<CliArgs> for Config {
                     _ => config.keyfile,
                 }
             }
+            Commands::Status { control_socket, .. }
+            | Commands::Peers { control_socket, .. }
+            | Commands::Ping { control_socket, .. } => {
+                config.control_socket = match control_socket {
+                    Some(arg) => Some(arg),
+                    _ => config.control_socket,
+                }
+            }
             Commands::Start {
                 ip_addr,
+                prefix_len,
+                network_prefix,
+                mtu,
+                routes,
                 interface_name,
                 listen_addr,
                 listen_port,
                 keyfile,
+                private_network,
+                identity_file,
+                identity_encoding,
+                control_socket,
+                enable_control_api,
+                metrics_addr,
                 enable_dht,
                 enable_mdns,
                 enable_upnp,
@@ -90,12 +245,36 @@ impl From<CliArgs> for Config {
                 enable_dcutr,
                 enable_autonat,
                 enable_identify,
+                enable_gossipsub,
+                enable_rendezvous,
+                enable_rendezvous_server,
+                enable_auto_relay,
+                enable_ping,
             } => {
                 config.ip_addr = match ip_addr {
-                    Some(arg) => arg,
+                    Some(arg) => Some(arg),
                     _ => config.ip_addr,
                 };
 
+                config.prefix_len = match prefix_len {
+                    Some(arg) => arg,
+                    _ => config.prefix_len,
+                };
+
+                config.network_prefix = match network_prefix {
+                    Some(arg) => arg,
+                    _ => config.network_prefix,
+                };
+
+                config.mtu = match mtu {
+                    Some(arg) => arg,
+                    _ => config.mtu,
+                };
+
+                if !routes.is_empty() {
+                    config.routes = routes;
+                }
+
                 config.interface_name = match interface_name {
                     Some(arg) => arg,
                     _ => config.interface_name,
@@ -116,6 +295,32 @@ impl From<CliArgs> for Config {
                     _ => config.keyfile,
                 };
 
+                config.private_network = match private_network {
+                    Some(arg) => Some(arg),
+                    _ => config.private_network,
+                };
+
+                config.identity_file = match identity_file {
+                    Some(arg) => arg,
+                    _ => config.identity_file,
+                };
+
+                config.identity_encoding = match identity_encoding {
+                    Some(arg) => arg,
+                    _ => config.identity_encoding,
+                };
+
+                config.control_socket = match (enable_control_api, control_socket) {
+                    (Some(false), _) => None,
+                    (_, Some(arg)) => Some(arg),
+                    _ => config.control_socket,
+                };
+
+                config.metrics_addr = match metrics_addr {
+                    Some(arg) => Some(arg),
+                    _ => config.metrics_addr,
+                };
+
                 config.discovery.dht = match enable_dht {
                     Some(arg) => arg,
                     _ => config.discovery.dht,
@@ -146,6 +351,31 @@ impl From<CliArgs> for Config {
                     _ => config.discovery.identify,
                 };
 
+                config.discovery.gossipsub = match enable_gossipsub {
+                    Some(arg) => arg,
+                    _ => config.discovery.gossipsub,
+                };
+
+                config.discovery.rendezvous = match enable_rendezvous {
+                    Some(arg) => arg,
+                    _ => config.discovery.rendezvous,
+                };
+
+                config.discovery.rendezvous_server = match enable_rendezvous_server {
+                    Some(arg) => arg,
+                    _ => config.discovery.rendezvous_server,
+                };
+
+                config.discovery.auto_relay = match enable_auto_relay {
+                    Some(arg) => arg,
+                    _ => config.discovery.auto_relay,
+                };
+
+                config.discovery.ping = match enable_ping {
+                    Some(arg) => arg,
+                    _ => config.discovery.ping,
+                };
+
                 config.enable_relay = match enable_relay {
                     Some(arg) => arg,
                     _ => config.enable_relay,
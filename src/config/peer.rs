@@ -0,0 +1,19 @@
+use ipnet::IpNet;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+/// A statically configured peer, similar to a wg-quick `[Peer]` section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Peer {
+    pub peer_id: PeerId,
+    /// Addresses to dial the peer on
+    #[serde(default)]
+    pub addresses: Vec<Multiaddr>,
+    /// Overlay addresses and subnets the peer may send from
+    #[serde(default)]
+    pub allowed_ips: Vec<IpNet>,
+    /// Interval in seconds for WireGuard keepalives
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
+}
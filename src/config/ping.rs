@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ping {
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Consecutive failed pings before the connection is closed.
+    pub max_failures: u32,
+}
+
+impl Default for Ping {
+    fn default() -> Self {
+        Ping {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(20),
+            max_failures: 3,
+        }
+    }
+}
+
+impl From<Ping> for libp2p::ping::Config {
+    fn from(ping: Ping) -> Self {
+        libp2p::ping::Config::new()
+            .with_interval(ping.interval)
+            .with_timeout(ping.timeout)
+    }
+}
@@ -0,0 +1,30 @@
+use crate::vpn::transport::RekeyLimits;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Limits after which the private network transport switches to a new key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rekey {
+    pub bytes: u64,
+    pub interval: Duration,
+}
+
+impl Default for Rekey {
+    fn default() -> Self {
+        let limits = RekeyLimits::default();
+        Rekey {
+            bytes: limits.bytes,
+            interval: limits.interval,
+        }
+    }
+}
+
+impl From<Rekey> for RekeyLimits {
+    fn from(rekey: Rekey) -> Self {
+        RekeyLimits {
+            bytes: rekey.bytes,
+            interval: rekey.interval,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use libp2p::relay::Config;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelayClient {
+    /// Relays to reserve a slot on while this node is not reachable.
+    pub max_reservations: usize,
+    /// Delay before reserving on a relay again, after its reservation failed.
+    pub retry_interval: Duration,
+}
+
+impl Default for RelayClient {
+    fn default() -> Self {
+        RelayClient {
+            max_reservations: 2,
+            retry_interval: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// At most `limit` requests per `interval`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub limit: NonZeroU32,
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Relay {
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub reservation_duration: Duration,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    pub max_circuit_duration: Duration,
+    pub max_circuit_bytes: u64,
+    pub reservation_rate_per_peer: Option<RateLimit>,
+    pub reservation_rate_per_ip: Option<RateLimit>,
+    pub circuit_rate_per_peer: Option<RateLimit>,
+    pub circuit_rate_per_ip: Option<RateLimit>,
+    /// Only relay for peers which proved their membership, in a private network.
+    pub members_only: bool,
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        let relay = Config::default();
+        Relay {
+            max_reservations: relay.max_reservations,
+            max_reservations_per_peer: relay.max_reservations_per_peer,
+            reservation_duration: relay.reservation_duration,
+            max_circuits: relay.max_circuits,
+            max_circuits_per_peer: relay.max_circuits_per_peer,
+            max_circuit_duration: relay.max_circuit_duration,
+            max_circuit_bytes: relay.max_circuit_bytes,
+            // The rate-limits of `Config::default()`, it does not expose them.
+            reservation_rate_per_peer: Some(RateLimit {
+                limit: NonZeroU32::new(30).unwrap(),
+                interval: Duration::from_secs(2 * 60),
+            }),
+            reservation_rate_per_ip: Some(RateLimit {
+                limit: NonZeroU32::new(60).unwrap(),
+                interval: Duration::from_secs(60),
+            }),
+            circuit_rate_per_peer: Some(RateLimit {
+                limit: NonZeroU32::new(30).unwrap(),
+                interval: Duration::from_secs(2 * 60),
+            }),
+            circuit_rate_per_ip: Some(RateLimit {
+                limit: NonZeroU32::new(60).unwrap(),
+                interval: Duration::from_secs(60),
+            }),
+            members_only: true,
+        }
+    }
+}
+
+impl From<Relay> for Config {
+    fn from(relay: Relay) -> Self {
+        let mut config = Config {
+            max_reservations: relay.max_reservations,
+            max_reservations_per_peer: relay.max_reservations_per_peer,
+            reservation_duration: relay.reservation_duration,
+            reservation_rate_limiters: vec![],
+            max_circuits: relay.max_circuits,
+            max_circuits_per_peer: relay.max_circuits_per_peer,
+            max_circuit_duration: relay.max_circuit_duration,
+            max_circuit_bytes: relay.max_circuit_bytes,
+            circuit_src_rate_limiters: vec![],
+        };
+
+        if let Some(rate) = relay.reservation_rate_per_peer {
+            config = config.reservation_rate_per_peer(rate.limit, rate.interval);
+        }
+        if let Some(rate) = relay.reservation_rate_per_ip {
+            config = config.reservation_rate_per_ip(rate.limit, rate.interval);
+        }
+        if let Some(rate) = relay.circuit_rate_per_peer {
+            config = config.circuit_src_per_peer(rate.limit, rate.interval);
+        }
+        if let Some(rate) = relay.circuit_rate_per_ip {
+            config = config.circuit_src_per_ip(rate.limit, rate.interval);
+        }
+
+        config
+    }
+}
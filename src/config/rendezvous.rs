@@ -0,0 +1,24 @@
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rendezvous {
+    /// Rendezvous-points to register at and discover the members through, ending in `/p2p/`.
+    pub points: Vec<Multiaddr>,
+    /// Lifetime of the registrations, renewed after half of it.
+    pub ttl: Duration,
+    /// Interval between discoveries at each rendezvous-point.
+    pub discover_interval: Duration,
+}
+
+impl Default for Rendezvous {
+    fn default() -> Self {
+        Rendezvous {
+            points: vec![],
+            ttl: Duration::from_secs(2 * 60 * 60),
+            discover_interval: Duration::from_secs(60),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+use std::{error, fmt, fs, io, path::Path};
+use tracing::warn;
+
+/// Prefix of environment-variables overriding configuration-keys.
+pub const ENV_PREFIX: &str = "VPNR_";
+
+/// Separator of nested keys in environment-variables, e.g. `VPNR_DISCOVERY__MDNS`.
+const ENV_SEPARATOR: &str = "__";
+
+/// Error loading the configuration
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration-file could not be read.
+    Io(String, io::Error),
+    /// The configuration-file is not valid YAML or JSON.
+    Parse(String, String),
+    /// A key is unknown or holds an invalid value.
+    Invalid(String, String),
+    /// An environment-variable holds an invalid value.
+    Env(String, String),
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "Error reading configuration {path}: {e}"),
+            Error::Parse(path, e) => write!(f, "Error parsing configuration {path}: {e}"),
+            Error::Invalid(key, e) => write!(f, "Invalid configuration-key `{key}`: {e}"),
+            Error::Env(name, e) => write!(f, "Invalid environment-variable `{name}`: {e}"),
+        }
+    }
+}
+
+/// Read a configuration-file, as JSON if the extension says so, as YAML otherwise.
+pub fn read_file(path: &Path) -> Result<Value, Error> {
+    let name = path.display().to_string();
+    let content = fs::read_to_string(path).map_err(|e| Error::Io(name.clone(), e))?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(|e| Error::Parse(name.clone(), e.to_string()))
+            .and_then(|json| {
+                serde_yaml::to_value(json).map_err(|e| Error::Parse(name, e.to_string()))
+            }),
+        _ => serde_yaml::from_str(&content).map_err(|e| Error::Parse(name, e.to_string())),
+    }
+}
+
+/// A `VPNR_*` environment-variable overriding a configuration-key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    path: Vec<String>,
+    raw: String,
+}
+
+impl Variable {
+    /// The key it overrides, e.g. `discovery.mdns`.
+    pub fn key(&self) -> String {
+        self.path.join(".")
+    }
+
+    /// Whether `key`, as reported by the deserializer, lies within the overridden key.
+    pub fn covers(&self, key: &str) -> bool {
+        let own = self.key();
+        key.strip_prefix(&own)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+    }
+}
+
+/// Collect the `VPNR_*` environment-variables naming a key of `known`, others are skipped with
+/// a warning.
+pub fn read_env(vars: impl Iterator<Item = (String, String)>, known: &Value) -> Vec<Variable> {
+    let mut variables = vec![];
+
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+
+        let path: Vec<String> = key
+            .to_lowercase()
+            .split(ENV_SEPARATOR)
+            .map(String::from)
+            .collect();
+        if !is_known(known, &path) {
+            warn!("Ignoring environment-variable {name}, no such configuration-key");
+            continue;
+        }
+
+        variables.push(Variable { name, path, raw });
+    }
+
+    variables
+}
+
+/// Nest the variables into a value. Those in `strings` are taken verbatim, the others are
+/// parsed as YAML.
+pub fn layer(variables: &[Variable], strings: &HashSet<String>) -> Value {
+    let mut value = Value::Mapping(Mapping::new());
+
+    for variable in variables {
+        let leaf = match strings.contains(&variable.name) {
+            true => Value::String(variable.raw.clone()),
+            false => serde_yaml::from_str(&variable.raw)
+                .unwrap_or_else(|_| Value::String(variable.raw.clone())),
+        };
+        insert(&mut value, &variable.path, leaf);
+    }
+
+    value
+}
+
+/// Whether `path` names a key of `known`, or lies within one holding a mapping.
+fn is_known(known: &Value, path: &[String]) -> bool {
+    match (path.split_first(), known) {
+        (None, _) => true,
+        (Some((key, rest)), Value::Mapping(mapping)) => mapping
+            .get(key.as_str())
+            .is_some_and(|child| is_known(child, rest)),
+        (Some(_), _) => false,
+    }
+}
+
+/// Merge `layer` into `base`, mappings are merged key by key, everything else is replaced.
+pub fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Mapping(base), Value::Mapping(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+fn insert(value: &mut Value, path: &[String], leaf: Value) {
+    let Some((key, rest)) = path.split_first() else {
+        *value = leaf;
+        return;
+    };
+
+    if !value.is_mapping() {
+        *value = Value::Mapping(Mapping::new());
+    }
+
+    if let Value::Mapping(mapping) = value {
+        let key = Value::String(key.clone());
+        let child = mapping
+            .entry(key)
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        insert(child, rest, leaf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known() -> Value {
+        serde_yaml::from_str(
+            "{ interface_name: vpnr0, network_id: null, discovery: { mdns: false } }",
+        )
+        .unwrap()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn nests_known_keys() {
+        let variables = read_env(
+            vars(&[("VPNR_DISCOVERY__MDNS", "true"), ("PATH", "/bin")]),
+            &known(),
+        );
+        assert_eq!(variables.len(), 1);
+        assert_eq!(variables[0].key(), "discovery.mdns");
+
+        let value = layer(&variables, &HashSet::new());
+        assert_eq!(value["discovery"]["mdns"], Value::Bool(true));
+    }
+
+    #[test]
+    fn skips_unknown_keys() {
+        let variables = read_env(
+            vars(&[("VPNR_FOO", "1"), ("VPNR_DISCOVERY__BAR", "1")]),
+            &known(),
+        );
+        assert!(variables.is_empty());
+    }
+
+    #[test]
+    fn takes_strings_verbatim() {
+        let variables = read_env(vars(&[("VPNR_INTERFACE_NAME", "123")]), &known());
+
+        let parsed = layer(&variables, &HashSet::new());
+        assert!(parsed["interface_name"].is_number());
+
+        let strings = HashSet::from([String::from("VPNR_INTERFACE_NAME")]);
+        let verbatim = layer(&variables, &strings);
+        assert_eq!(
+            verbatim["interface_name"],
+            Value::String(String::from("123"))
+        );
+    }
+
+    #[test]
+    fn covers_nested_keys() {
+        let variables = read_env(vars(&[("VPNR_DISCOVERY", "{ mdns: 1 }")]), &known());
+
+        assert!(variables[0].covers("discovery"));
+        assert!(variables[0].covers("discovery.mdns"));
+        assert!(!variables[0].covers("discovery_other"));
+    }
+}
@@ -0,0 +1,758 @@
+use ipnet::IpNet;
+use libp2p::{
+    Multiaddr, PeerId, Swarm, autonat, core::ConnectedPoint, multiaddr::Protocol,
+    swarm::ConnectionId,
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{error, fmt, fs, io};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::VpnBehaviour;
+use crate::config::acl::Mode;
+use crate::dht::Metrics;
+use crate::latency::Latency;
+use crate::relay_server::PeerStats;
+use crate::reload;
+use crate::swarm::Node;
+use crate::vpn::{behaviour::Traffic, packet};
+
+pub const VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The node failed to carry out the request.
+pub const FAILED: i64 = -32000;
+
+/// Time to wait for the echo-reply of an overlay-ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const PING_PAYLOAD: &[u8] = b"vpnr overlay-ping";
+
+/// A JSON-RPC request, one per line. Requests without an `id` are not answered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// A JSON-RPC response, carrying either the result or the error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+/// Error of a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Error {
+    pub code: i64,
+    pub message: String,
+}
+
+impl error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl Error {
+    fn new(code: i64, message: impl fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Response {
+    fn new(id: Value, outcome: Result<Value, Error>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DialParams {
+    address: Multiaddr,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeerParams {
+    peer_id: PeerId,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PingParams {
+    /// A PeerId or overlay-address.
+    target: String,
+}
+
+/// The identity and reachability of this node, as listed by `status`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub peer_id: PeerId,
+    pub network_id: String,
+    /// The fingerprint of the pre-shared key, `None` in a public network.
+    pub fingerprint: Option<String>,
+    /// The local overlay-addresses.
+    pub overlay: Vec<IpAddr>,
+    /// `public`, `private` or `unknown` as found by AutoNAT, `disabled` without it.
+    pub nat_status: String,
+    /// The address AutoNAT confirmed this node reachable on.
+    pub public_address: Option<Multiaddr>,
+    pub connected_peers: usize,
+    /// The members heard of through the announcements.
+    pub members: usize,
+}
+
+/// A connected peer, as listed by `peers`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Peer {
+    pub peer_id: PeerId,
+    /// Whether the peer proved its membership of the network.
+    pub member: bool,
+    /// The name the peer announced.
+    pub name: Option<String>,
+    /// The overlay-addresses of the peer's handshake.
+    pub addresses: Vec<IpAddr>,
+    pub traffic: Traffic,
+    pub connections: Vec<Connection>,
+}
+
+/// How a connection reaches the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// TCP, directly.
+    Direct,
+    /// QUIC, directly.
+    Quic,
+    /// Through a relay's circuit.
+    Relayed,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Direct => write!(f, "direct"),
+            Kind::Quic => write!(f, "quic"),
+            Kind::Relayed => write!(f, "relayed"),
+        }
+    }
+}
+
+/// A connection to a peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection {
+    pub id: String,
+    pub address: Multiaddr,
+    /// Whether this node dialed the connection.
+    pub outbound: bool,
+    pub kind: Kind,
+    /// The smoothed round-trip-time, once measured.
+    pub rtt: Option<Duration>,
+}
+
+/// The echo-reply to an overlay-ping.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingReply {
+    pub peer_id: PeerId,
+    pub address: IpAddr,
+    pub sequence: u16,
+    pub rtt: Duration,
+}
+
+/// An overlay-ping awaiting its echo-reply.
+struct Ping {
+    address: IpAddr,
+    sent: Instant,
+    id: Value,
+    reply: oneshot::Sender<Response>,
+}
+
+/// A subnet routed to a peer.
+#[derive(Debug, Serialize)]
+pub struct Route {
+    pub subnet: IpNet,
+    pub peer_id: PeerId,
+}
+
+/// The routes of the overlay, as listed by `routes`.
+#[derive(Debug, Serialize)]
+pub struct Routes {
+    /// Subnets advertised as reachable through this node.
+    pub advertised: Vec<IpNet>,
+    pub peers: Vec<Route>,
+}
+
+/// The addresses of this node, as listed by `addresses`.
+#[derive(Debug, Serialize)]
+pub struct Addresses {
+    pub peer_id: PeerId,
+    /// The local overlay-addresses.
+    pub overlay: Vec<IpAddr>,
+    pub listen: Vec<Multiaddr>,
+    pub external: Vec<Multiaddr>,
+}
+
+/// The access-control lists, as listed by `acl`.
+#[derive(Debug, Serialize)]
+pub struct Access {
+    pub mode: Mode,
+    pub allow: BTreeSet<PeerId>,
+    pub deny: BTreeSet<PeerId>,
+}
+
+/// The counters of the node, as listed by `stats`.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub connected_peers: usize,
+    pub connections: usize,
+    /// The members heard of through the announcements.
+    pub members: usize,
+    pub routes: usize,
+    pub dht: Metrics,
+    pub relay: HashMap<PeerId, PeerStats>,
+    pub latencies: BTreeMap<PeerId, Latency>,
+}
+
+type Call = (Request, oneshot::Sender<Response>);
+
+/// Serves the control-API on a Unix-socket, the requests are handed to the event-loop through
+/// [`Control::next`].
+pub struct Control {
+    path: Option<PathBuf>,
+    /// Locked while serving, other nodes find the socket in use.
+    lock: Option<fs::File>,
+    calls: Option<mpsc::Receiver<Call>>,
+    /// The established connections, by their id.
+    connections: HashMap<ConnectionId, (PeerId, ConnectedPoint)>,
+    /// Identifier of the echo-requests of the overlay-pings.
+    identifier: u16,
+    sequence: u16,
+    /// Overlay-pings awaiting their echo-reply, by sequence-number.
+    pings: HashMap<u16, Ping>,
+}
+
+impl Control {
+    /// Listen on the Unix-socket at `path`, accessible to the owner only. `None` disables the
+    /// API.
+    pub fn bind(path: Option<&str>) -> io::Result<Self> {
+        let Some(path) = path.map(PathBuf::from) else {
+            println!("Not serving the control-API ...");
+            return Ok(Self {
+                path: None,
+                lock: None,
+                calls: None,
+                connections: HashMap::new(),
+                identifier: 0,
+                sequence: 0,
+                pings: HashMap::new(),
+            });
+        };
+
+        // A socket without a lock held was left behind by a node which did not shut down, and
+        // is replaced.
+        let lock_path = lock_path(&path);
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(&lock_path)?;
+        let locked = match lock.try_lock() {
+            // The node closing removed the file after we opened it, another may lock a new one.
+            Ok(()) => is_file_at(&lock, &lock_path)?,
+            Err(fs::TryLockError::WouldBlock) => false,
+            Err(fs::TryLockError::Error(e)) => return Err(e),
+        };
+        if !locked {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is served by another node", path.display()),
+            ));
+        }
+
+        let listener = bind_private(&path)?;
+        info!("Serving the control-API on {}", path.display());
+
+        let (sender, calls) = mpsc::channel(16);
+        tokio::spawn(accept(listener, sender));
+
+        Ok(Self {
+            path: Some(path),
+            lock: Some(lock),
+            calls: Some(calls),
+            connections: HashMap::new(),
+            identifier: rand::random(),
+            sequence: 0,
+            pings: HashMap::new(),
+        })
+    }
+
+    /// The next request, with the channel to answer it on.
+    pub async fn next(&mut self) -> Call {
+        if let Some(calls) = self.calls.as_mut()
+            && let Some(call) = calls.recv().await
+        {
+            return call;
+        }
+        std::future::pending().await
+    }
+
+    pub fn on_connected(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        endpoint: ConnectedPoint,
+    ) {
+        self.connections.insert(connection_id, (peer_id, endpoint));
+    }
+
+    pub fn on_closed(&mut self, connection_id: &ConnectionId) {
+        self.connections.remove(connection_id);
+    }
+
+    /// Answer an overlay-ping if `packet` is its echo-reply, which is then not passed on to the
+    /// interface.
+    pub fn on_packet(&mut self, peer_id: PeerId, packet: &[u8]) -> bool {
+        let Some((identifier, sequence)) = packet::echo_reply(packet) else {
+            return false;
+        };
+        if identifier != self.identifier {
+            return false;
+        }
+        let Some(ping) = self.pings.remove(&sequence) else {
+            return false;
+        };
+
+        let reply = PingReply {
+            peer_id,
+            address: ping.address,
+            sequence,
+            rtt: ping.sent.elapsed(),
+        };
+        let _ = ping.reply.send(Response::new(ping.id, result(reply)));
+        true
+    }
+
+    /// Fail the overlay-pings which were not answered in time.
+    pub fn expire(&mut self) {
+        let expired: Vec<u16> = self
+            .pings
+            .iter()
+            .filter(|(_, ping)| ping.sent.elapsed() >= PING_TIMEOUT)
+            .map(|(sequence, _)| *sequence)
+            .collect();
+
+        for sequence in expired {
+            if let Some(ping) = self.pings.remove(&sequence) {
+                let error = Error::new(FAILED, format!("No reply from {}", ping.address));
+                let _ = ping.reply.send(Response::new(ping.id, Err(error)));
+            }
+        }
+    }
+
+    /// Stop serving and remove the socket and its lock-file.
+    pub fn close(self) {
+        let Some(path) = self.path else {
+            return;
+        };
+        // The lock-file is removed while still locked, so no other node locks it in between.
+        for path in [path.clone(), lock_path(&path)] {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Error removing {}, {e}", path.display());
+            }
+        }
+    }
+}
+
+/// The file locked while serving on `path`.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+/// Whether `file` is still the one at `path`.
+fn is_file_at(file: &fs::File, path: &Path) -> io::Result<bool> {
+    let opened = file.metadata()?;
+    Ok(fs::metadata(path).is_ok_and(|at| at.dev() == opened.dev() && at.ino() == opened.ino()))
+}
+
+/// Bind the socket in a directory only the owner can enter, so nobody else can connect before
+/// its permissions are restricted, then move it to `path`.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Socket-path without name"))?;
+    let mut staging = name.to_os_string();
+    staging.push(format!(".{}", std::process::id()));
+    let staging = path.with_file_name(staging);
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join(name);
+    let listener = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+
+    let _ = fs::remove_file(&staged);
+    if let Err(e) = fs::remove_dir(&staging) {
+        warn!("Error removing {}, {e}", staging.display());
+    }
+    listener
+}
+
+async fn accept(listener: UnixListener, sender: mpsc::Sender<Call>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve(stream, sender.clone()));
+            }
+            Err(e) => warn!("Error accepting on the control-socket, {e}"),
+        }
+    }
+}
+
+/// Answer the requests of a client, one per line, until it disconnects.
+async fn serve(stream: UnixStream, sender: mpsc::Sender<Call>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Error reading from control-client, {e}");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) if request.jsonrpc != VERSION => Response::new(
+                request.id.unwrap_or(Value::Null),
+                Err(Error::new(INVALID_REQUEST, "Expected JSON-RPC 2.0")),
+            ),
+            Ok(request) => {
+                let notification = request.id.is_none();
+                let (reply, response) = oneshot::channel();
+                if sender.send((request, reply)).await.is_err() {
+                    break;
+                }
+                match response.await {
+                    Ok(_) if notification => continue,
+                    Ok(response) => response,
+                    Err(_) => break,
+                }
+            }
+            Err(e) if e.is_syntax() || e.is_eof() => {
+                Response::new(Value::Null, Err(Error::new(PARSE_ERROR, e)))
+            }
+            Err(e) => Response::new(Value::Null, Err(Error::new(INVALID_REQUEST, e))),
+        };
+
+        let mut data = match serde_json::to_vec(&response) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Error encoding control-response, {e}");
+                break;
+            }
+        };
+        data.push(b'\n');
+        if let Err(e) = writer.write_all(&data).await {
+            debug!("Error writing to control-client, {e}");
+            break;
+        }
+    }
+}
+
+/// Carry out a request on the running node, answering on `reply`. Overlay-pings are answered
+/// once their echo-reply arrives or they expire.
+pub(crate) fn handle(
+    swarm: &mut Swarm<VpnBehaviour>,
+    node: &mut Node,
+    request: Request,
+    reply: oneshot::Sender<Response>,
+) {
+    debug!("Control-request {}", request.method);
+    let id = request.id.unwrap_or(Value::Null);
+
+    let outcome = match request.method.as_str() {
+        "ping" => {
+            match params::<PingParams>(request.params)
+                .and_then(|PingParams { target }| ping(swarm, node, &target))
+            {
+                Ok((sequence, address)) => {
+                    let sent = Instant::now();
+                    let ping = Ping {
+                        address,
+                        sent,
+                        id,
+                        reply,
+                    };
+                    node.control.pings.insert(sequence, ping);
+                    return;
+                }
+                Err(e) => Err(e),
+            }
+        }
+        "status" => result(status(swarm, node)),
+        "peers" => result(peers(swarm, node)),
+        "routes" => result(Routes {
+            advertised: swarm.behaviour().vpn.advertised_routes().to_vec(),
+            peers: swarm
+                .behaviour()
+                .vpn
+                .routing_table()
+                .routes()
+                .into_iter()
+                .map(|(subnet, peer_id)| Route { subnet, peer_id })
+                .collect(),
+        }),
+        "addresses" => result(Addresses {
+            peer_id: *swarm.local_peer_id(),
+            overlay: swarm.behaviour().vpn.addresses().to_vec(),
+            listen: swarm.listeners().cloned().collect(),
+            external: swarm.external_addresses().cloned().collect(),
+        }),
+        "stats" => result(Stats {
+            connected_peers: swarm.connected_peers().count(),
+            connections: node.control.connections.len(),
+            members: node.gossip.members().count(),
+            routes: swarm.behaviour().vpn.routing_table().routes().len(),
+            dht: node.dht.metrics().clone(),
+            relay: node.relay_server.stats(),
+            latencies: node.latencies.table(),
+        }),
+        "dial" => params::<DialParams>(request.params).and_then(|DialParams { address }| {
+            info!("Dialing {} on request", address);
+            swarm
+                .dial(address)
+                .map_err(|e| Error::new(FAILED, e))
+                .map(|()| Value::Null)
+        }),
+        "disconnect" => params::<PeerParams>(request.params).and_then(|PeerParams { peer_id }| {
+            info!("Disconnecting {} on request", peer_id);
+            swarm
+                .disconnect_peer_id(peer_id)
+                .map_err(|()| Error::new(FAILED, format!("Not connected to {peer_id}")))
+                .map(|()| Value::Null)
+        }),
+        "acl" => {
+            let lists = node.acl.lists();
+            result(Access {
+                mode: node.acl.mode(),
+                allow: lists.allow,
+                deny: lists.deny,
+            })
+        }
+        "block" => params::<PeerParams>(request.params).and_then(|PeerParams { peer_id }| {
+            info!("Blocking {} on request", peer_id);
+            node.acl
+                .deny(peer_id, swarm)
+                .map_err(|e| Error::new(FAILED, e))
+                .map(|()| Value::Null)
+        }),
+        "unblock" => params::<PeerParams>(request.params).and_then(|PeerParams { peer_id }| {
+            info!("Unblocking {} on request", peer_id);
+            node.acl
+                .undeny(peer_id, swarm)
+                .map_err(|e| Error::new(FAILED, e))
+                .map(|()| Value::Null)
+        }),
+        "allow" => params::<PeerParams>(request.params).and_then(|PeerParams { peer_id }| {
+            info!("Allowing {} on request", peer_id);
+            node.acl
+                .allow(peer_id, swarm)
+                .map_err(|e| Error::new(FAILED, e))
+                .map(|()| Value::Null)
+        }),
+        "disallow" => params::<PeerParams>(request.params).and_then(|PeerParams { peer_id }| {
+            info!("Disallowing {} on request", peer_id);
+            node.acl
+                .disallow(peer_id, swarm)
+                .map_err(|e| Error::new(FAILED, e))
+                .map(|()| Value::Null)
+        }),
+        "reload" => reload::reload(swarm, node)
+            .map_err(|e| Error::new(FAILED, e))
+            .and_then(result),
+        method => Err(Error::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {method}"),
+        )),
+    };
+
+    // The client may have disconnected meanwhile.
+    let _ = reply.send(Response::new(id, outcome));
+}
+
+/// Send an echo-request through the tunnel to `target`, a PeerId or overlay-address. Returns
+/// its sequence-number and destination.
+fn ping(
+    swarm: &mut Swarm<VpnBehaviour>,
+    node: &mut Node,
+    target: &str,
+) -> Result<(u16, IpAddr), Error> {
+    let candidates: Vec<IpAddr> = match (target.parse::<IpAddr>(), target.parse::<PeerId>()) {
+        (Ok(address), _) => vec![address],
+        (_, Ok(peer_id)) => match swarm.behaviour().vpn.remote(&peer_id) {
+            Some(hello) => hello.addresses.clone(),
+            None => node
+                .gossip
+                .member(&peer_id)
+                .map(|member| member.announcement.addresses.clone())
+                .unwrap_or_default(),
+        },
+        _ => {
+            return Err(Error::new(
+                INVALID_PARAMS,
+                format!("{target} is neither a PeerId nor an IP-address"),
+            ));
+        }
+    };
+
+    let local = swarm.behaviour().vpn.addresses().to_vec();
+    let (source, destination) = candidates
+        .iter()
+        .find_map(|destination| {
+            local
+                .iter()
+                .find(|source| source.is_ipv4() == destination.is_ipv4())
+                .map(|source| (*source, *destination))
+        })
+        .ok_or_else(|| Error::new(FAILED, format!("No overlay-address known for {target}")))?;
+
+    let control = &mut node.control;
+    control.sequence = control.sequence.wrapping_add(1);
+    let packet = packet::echo_request(
+        source,
+        destination,
+        control.identifier,
+        control.sequence,
+        PING_PAYLOAD,
+    )
+    .ok_or_else(|| Error::new(INTERNAL_ERROR, "Mismatching IP-versions"))?;
+
+    swarm.behaviour_mut().vpn.send_packet(packet);
+    Ok((control.sequence, destination))
+}
+
+fn status(swarm: &Swarm<VpnBehaviour>, node: &Node) -> Status {
+    let (nat_status, public_address) = match swarm.behaviour().autonat.as_ref() {
+        Some(autonat) => match autonat.nat_status() {
+            autonat::NatStatus::Public(address) => ("public", Some(address)),
+            autonat::NatStatus::Private => ("private", None),
+            autonat::NatStatus::Unknown => ("unknown", None),
+        },
+        None => ("disabled", None),
+    };
+
+    Status {
+        peer_id: *swarm.local_peer_id(),
+        network_id: node.started.network_id(node.network.as_ref()),
+        fingerprint: node
+            .network
+            .as_ref()
+            .map(|network| network.fingerprint().to_string()),
+        overlay: swarm.behaviour().vpn.addresses().to_vec(),
+        nat_status: nat_status.to_string(),
+        public_address,
+        connected_peers: swarm.connected_peers().count(),
+        members: node.gossip.members().count(),
+    }
+}
+
+fn peers(swarm: &Swarm<VpnBehaviour>, node: &Node) -> Vec<Peer> {
+    let mut connections: BTreeMap<PeerId, Vec<Connection>> = BTreeMap::new();
+    for (connection_id, (peer_id, endpoint)) in &node.control.connections {
+        connections.entry(*peer_id).or_default().push(Connection {
+            id: connection_id.to_string(),
+            address: endpoint.get_remote_address().clone(),
+            outbound: endpoint.is_dialer(),
+            kind: match endpoint.is_relayed() {
+                true => Kind::Relayed,
+                false
+                    if endpoint
+                        .get_remote_address()
+                        .iter()
+                        .any(|protocol| protocol == Protocol::QuicV1) =>
+                {
+                    Kind::Quic
+                }
+                false => Kind::Direct,
+            },
+            rtt: node
+                .latencies
+                .get(connection_id)
+                .map(|latency| latency.smoothed_rtt),
+        });
+    }
+
+    connections
+        .into_iter()
+        .map(|(peer_id, connections)| Peer {
+            peer_id,
+            member: swarm.behaviour().vpn.is_member(&peer_id),
+            name: node
+                .gossip
+                .member(&peer_id)
+                .and_then(|member| member.announcement.name.clone()),
+            addresses: swarm
+                .behaviour()
+                .vpn
+                .remote(&peer_id)
+                .map(|hello| hello.addresses.clone())
+                .unwrap_or_default(),
+            traffic: swarm.behaviour().vpn.traffic(&peer_id),
+            connections,
+        })
+        .collect()
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    serde_json::from_value(params).map_err(|e| Error::new(INVALID_PARAMS, e))
+}
+
+fn result(value: impl Serialize) -> Result<Value, Error> {
+    serde_json::to_value(value).map_err(|e| Error::new(INTERNAL_ERROR, e))
+}
@@ -0,0 +1,572 @@
+use libp2p::{
+    Multiaddr, PeerId,
+    core::SignedEnvelope,
+    identity::{Keypair, PublicKey},
+    kad::{self, store::RecordStore},
+};
+use prometheus_client::encoding::EncodeLabelValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use std::{error, fmt};
+use tracing::{debug, warn};
+
+use crate::config::kademlia::Kademlia;
+use crate::metrics;
+use crate::network::NetworkKey;
+
+/// Domain-separation of the signature on address-records.
+const DOMAIN: &str = "vpnr-address-record";
+const PAYLOAD_TYPE: &[u8] = b"/vpnr/address-record/1";
+
+/// Delay before publishing, so addresses learned in a burst are published once.
+const PUBLISH_DELAY: Duration = Duration::from_secs(2);
+
+/// Maps an overlay-address to the PeerId owning it and the addresses to dial it on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressRecord {
+    pub address: IpAddr,
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    /// In a private network, proof that the signer holds the network's key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub membership_proof: Option<[u8; 32]>,
+}
+
+/// Error reading an address-record
+#[derive(Debug)]
+pub enum Error {
+    /// The signed envelope is malformed or its signature invalid.
+    Envelope(String),
+    /// The payload can not be decoded.
+    Malformed(serde_json::Error),
+    /// The record was signed by another peer than the one it names.
+    ForeignSigner(PeerId),
+    /// The record is stored under a key it does not belong to.
+    KeyMismatch,
+    /// The record lacks a valid proof of its signer's membership in the private network.
+    NotAMember(PeerId),
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Malformed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Envelope(e) => write!(f, "Invalid envelope: {e}"),
+            Error::Malformed(e) => write!(f, "Malformed record: {e}"),
+            Error::ForeignSigner(signer) => write!(f, "Record signed by {signer}"),
+            Error::KeyMismatch => write!(f, "Record stored under a foreign key"),
+            Error::NotAMember(signer) => write!(f, "Record signer {signer} is not a member"),
+        }
+    }
+}
+
+/// The DHT-key of the record for `address`.
+pub fn record_key(address: &IpAddr) -> kad::RecordKey {
+    kad::RecordKey::new(&format!("/vpnr/ip/{address}"))
+}
+
+/// The DHT-key of the public key of `peer_id`.
+fn pk_record_key(peer_id: &PeerId) -> kad::RecordKey {
+    let mut key = vec![];
+    key.extend_from_slice("/pk/".as_bytes());
+    key.extend_from_slice(peer_id.to_bytes().as_slice());
+    kad::RecordKey::new(&key)
+}
+
+/// Check a record received from another node is one of vpnr's and authentic, in a private
+/// network that an address-record proves its signer's membership.
+pub fn validate(record: &kad::Record, network: Option<&NetworkKey>) -> Result<(), Error> {
+    let key = record.key.as_ref();
+    if key.starts_with(b"/pk/") {
+        let public_key = PublicKey::try_decode_protobuf(&record.value)
+            .map_err(|e| Error::Envelope(e.to_string()))?;
+        return match pk_record_key(&public_key.to_peer_id()) == record.key {
+            true => Ok(()),
+            false => Err(Error::KeyMismatch),
+        };
+    }
+
+    let address_record = AddressRecord::verify(&record.value)?;
+    address_record.verify_membership(network)?;
+    match record_key(&address_record.address) == record.key {
+        true => Ok(()),
+        false => Err(Error::KeyMismatch),
+    }
+}
+
+impl AddressRecord {
+    /// Encode the record in an envelope signed by `keypair`.
+    pub fn sign(&self, keypair: &Keypair) -> Result<Vec<u8>, Error> {
+        let payload = serde_json::to_vec(self).map_err(Error::Malformed)?;
+        let envelope =
+            SignedEnvelope::new(keypair, DOMAIN.to_string(), PAYLOAD_TYPE.to_vec(), payload)
+                .map_err(|e| Error::Envelope(e.to_string()))?;
+
+        Ok(envelope.into_protobuf_encoding())
+    }
+
+    /// Decode a record, checking it is signed by the peer it names.
+    pub fn verify(bytes: &[u8]) -> Result<Self, Error> {
+        let envelope = SignedEnvelope::from_protobuf_encoding(bytes)
+            .map_err(|e| Error::Envelope(e.to_string()))?;
+        let (payload, signer) = envelope
+            .payload_and_signing_key(DOMAIN.to_string(), PAYLOAD_TYPE)
+            .map_err(|e| Error::Envelope(e.to_string()))?;
+
+        let record: AddressRecord = serde_json::from_slice(payload).map_err(Error::Malformed)?;
+        if signer.to_peer_id() != record.peer_id {
+            return Err(Error::ForeignSigner(signer.to_peer_id()));
+        }
+
+        Ok(record)
+    }
+
+    /// Check the record proves its signer is a member of `network`, if private.
+    pub fn verify_membership(&self, network: Option<&NetworkKey>) -> Result<(), Error> {
+        let Some(network) = network else {
+            return Ok(());
+        };
+
+        match &self.membership_proof {
+            Some(proof) if network.verify_record(proof, &self.peer_id) => Ok(()),
+            _ => Err(Error::NotAMember(self.peer_id)),
+        }
+    }
+}
+
+/// Outcome of DHT-queries, reported to the event-loop.
+#[derive(Debug)]
+pub enum Event {
+    /// The owner of `destination` was found.
+    Resolved {
+        destination: IpAddr,
+        record: AddressRecord,
+    },
+    /// The owner of `destination` could not be found.
+    Unresolved { destination: IpAddr },
+    /// A query failed.
+    QueryFailed { query: Query, error: String },
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, EncodeLabelValue)]
+#[serde(rename_all = "snake_case")]
+pub enum Query {
+    Bootstrap,
+    GetClosestPeers,
+    GetRecord,
+    PutRecord,
+    RepublishRecord,
+    Providers,
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Query::Bootstrap => write!(f, "bootstrap"),
+            Query::GetClosestPeers => write!(f, "get_closest_peers"),
+            Query::GetRecord => write!(f, "get_record"),
+            Query::PutRecord => write!(f, "put_record"),
+            Query::RepublishRecord => write!(f, "republish_record"),
+            Query::Providers => write!(f, "providers"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Counter {
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+/// Finished queries by kind and outcome.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Metrics {
+    pub bootstrap: Counter,
+    pub get_closest_peers: Counter,
+    pub get_record: Counter,
+    pub put_record: Counter,
+    pub republish_record: Counter,
+    pub providers: Counter,
+    /// Records put by non-members or failing verification, and provider-records added by
+    /// non-members.
+    pub rejected_records: u64,
+}
+
+impl Metrics {
+    fn counter(&mut self, query: Query) -> &mut Counter {
+        match query {
+            Query::Bootstrap => &mut self.bootstrap,
+            Query::GetClosestPeers => &mut self.get_closest_peers,
+            Query::GetRecord => &mut self.get_record,
+            Query::PutRecord => &mut self.put_record,
+            Query::RepublishRecord => &mut self.republish_record,
+            Query::Providers => &mut self.providers,
+        }
+    }
+}
+
+/// Keeps the DHT healthy: bootstraps after the first peer and periodically afterwards,
+/// publishes the local records and republishes them before they expire, and resolves the
+/// overlay-addresses of other nodes.
+pub struct Dht {
+    keypair: Keypair,
+    config: Kademlia,
+    /// The key of the private network, records have to prove their signer holds it.
+    network: Option<NetworkKey>,
+    /// Lookups in flight, by the overlay-address they resolve.
+    lookups: HashMap<kad::QueryId, IpAddr>,
+    /// Next bootstrap, `None` until the routing-table has a peer.
+    bootstrap_at: Option<Instant>,
+    /// Next publication of the records, `None` until the local addresses are known.
+    publish_at: Option<Instant>,
+    metrics: Metrics,
+    /// The counters exported to Prometheus.
+    exported: metrics::Metrics,
+}
+
+impl Dht {
+    pub fn new(
+        keypair: Keypair,
+        config: Kademlia,
+        network: Option<NetworkKey>,
+        exported: metrics::Metrics,
+    ) -> Self {
+        check(&config);
+
+        Self {
+            keypair,
+            config,
+            network,
+            lookups: HashMap::new(),
+            bootstrap_at: None,
+            publish_at: None,
+            metrics: Metrics::default(),
+            exported,
+        }
+    }
+
+    /// Apply a reloaded configuration, the intervals take effect after the current ones.
+    pub fn set_config(&mut self, config: Kademlia) {
+        check(&config);
+        self.config = config;
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Publish the records once the local addresses settled.
+    pub fn schedule_publish(&mut self) {
+        self.publish_at = Some(Instant::now() + PUBLISH_DELAY);
+    }
+
+    /// A peer was added to the routing-table, bootstrap if this is the first.
+    pub fn on_routing_updated(&mut self) {
+        if self.bootstrap_at.is_none() {
+            self.bootstrap_at = Some(Instant::now());
+        }
+    }
+
+    /// Run the bootstraps and publications which are due.
+    pub fn maintain(
+        &mut self,
+        kademlia: &mut kad::Behaviour<kad::store::MemoryStore>,
+        overlay: &[IpAddr],
+        addresses: Vec<Multiaddr>,
+    ) -> Vec<Event> {
+        let now = Instant::now();
+        let mut events = vec![];
+
+        if self.bootstrap_at.is_some_and(|at| at <= now) {
+            self.bootstrap_at = Some(now + self.config.bootstrap_interval);
+            debug!("Bootstrapping DHT");
+            if let Err(e) = kademlia.bootstrap() {
+                events.push(self.failed(Query::Bootstrap, e.to_string()));
+            }
+        }
+
+        if self.publish_at.is_some_and(|at| at <= now) {
+            self.publish_at = Some(now + self.config.republish_interval);
+            events.extend(self.publish(kademlia, overlay, addresses));
+        }
+
+        events
+    }
+
+    fn failed(&mut self, query: Query, error: String) -> Event {
+        self.count(query, false);
+        Event::QueryFailed { query, error }
+    }
+
+    fn count(&mut self, query: Query, succeeded: bool) {
+        let counter = self.metrics.counter(query);
+        match succeeded {
+            true => counter.succeeded += 1,
+            false => counter.failed += 1,
+        }
+        self.exported.dht_query(query, succeeded);
+    }
+
+    fn reject(&mut self) {
+        self.metrics.rejected_records += 1;
+        self.exported.dht_rejected_record();
+    }
+
+    /// Put the public key and a record for each overlay-address, pointing to `addresses`.
+    fn publish(
+        &mut self,
+        kademlia: &mut kad::Behaviour<kad::store::MemoryStore>,
+        overlay: &[IpAddr],
+        addresses: Vec<Multiaddr>,
+    ) -> Vec<Event> {
+        let peer_id = self.keypair.public().to_peer_id();
+        let mut records = vec![];
+
+        records.push(kad::Record::new(
+            pk_record_key(&peer_id),
+            self.keypair.public().encode_protobuf(),
+        ));
+
+        for address in overlay {
+            let record = AddressRecord {
+                address: *address,
+                peer_id,
+                addresses: addresses.clone(),
+                membership_proof: self
+                    .network
+                    .as_ref()
+                    .map(|network| network.record_proof(&peer_id)),
+            };
+
+            match record.sign(&self.keypair) {
+                Ok(value) => records.push(kad::Record::new(record_key(address), value)),
+                Err(e) => warn!("Error signing address-record, {e}"),
+            }
+        }
+
+        let mut events = vec![];
+        for mut record in records {
+            record.publisher = Some(peer_id);
+            record.expires = Some(Instant::now() + self.config.record_ttl);
+
+            debug!("Publishing record {:?}", record.key);
+            if let Err(e) = kademlia.put_record(record, kad::Quorum::One) {
+                events.push(self.failed(Query::PutRecord, e.to_string()));
+            }
+        }
+        events
+    }
+
+    /// Store a record another node put, if it verifies. Address-records prove the membership
+    /// of their signer, so they are accepted from any node replicating them; the public keys
+    /// carry no proof and are accepted from members only.
+    pub fn on_put_record(
+        &mut self,
+        kademlia: &mut kad::Behaviour<kad::store::MemoryStore>,
+        source: PeerId,
+        record: kad::Record,
+        member: bool,
+    ) {
+        if !member && record.key.as_ref().starts_with(b"/pk/") {
+            debug!("Rejecting public key from {}, not a member", source);
+            self.reject();
+            return;
+        }
+
+        if let Err(e) = validate(&record, self.network.as_ref()) {
+            warn!("Rejecting record {:?} from {}, {e}", record.key, source);
+            self.reject();
+            return;
+        }
+
+        if let Err(e) = kademlia.store_mut().put(record) {
+            warn!("Error storing record from {}, {e}", source);
+        }
+    }
+
+    /// Store a provider-record another node added, if it is a member.
+    pub fn on_add_provider(
+        &mut self,
+        kademlia: &mut kad::Behaviour<kad::store::MemoryStore>,
+        source: PeerId,
+        record: kad::ProviderRecord,
+        member: bool,
+    ) {
+        if !member {
+            debug!("Rejecting provider-record from {}, not a member", source);
+            self.reject();
+            return;
+        }
+
+        if let Err(e) = kademlia.store_mut().add_provider(record) {
+            warn!("Error storing provider-record from {}, {e}", source);
+        }
+    }
+
+    /// Start looking up the owner of `address`.
+    pub fn lookup(
+        &mut self,
+        kademlia: &mut kad::Behaviour<kad::store::MemoryStore>,
+        address: IpAddr,
+    ) {
+        debug!("Looking up overlay-address {}", address);
+        let query_id = kademlia.get_record(record_key(&address));
+        self.lookups.insert(query_id, address);
+    }
+
+    /// Count a step of a query and report its outcome.
+    pub fn on_query_progressed(
+        &mut self,
+        query_id: kad::QueryId,
+        result: kad::QueryResult,
+        last: bool,
+    ) -> Vec<Event> {
+        fn outcome<T, E: fmt::Display>(result: &Result<T, E>) -> Result<(), String> {
+            result.as_ref().map(|_| ()).map_err(|e| e.to_string())
+        }
+
+        let mut events = vec![];
+        let (query, outcome) = match result {
+            kad::QueryResult::Bootstrap(result) => (Query::Bootstrap, outcome(&result)),
+            kad::QueryResult::GetClosestPeers(result) => (Query::GetClosestPeers, outcome(&result)),
+            kad::QueryResult::GetProviders(result) => (Query::Providers, outcome(&result)),
+            kad::QueryResult::StartProviding(result) => (Query::Providers, outcome(&result)),
+            kad::QueryResult::RepublishProvider(result) => (Query::Providers, outcome(&result)),
+            kad::QueryResult::PutRecord(result) => (Query::PutRecord, outcome(&result)),
+            kad::QueryResult::RepublishRecord(result) => (Query::RepublishRecord, outcome(&result)),
+            kad::QueryResult::GetRecord(result) => {
+                let outcome = outcome(&result);
+                events.extend(self.on_get_record(query_id, result, last));
+                (Query::GetRecord, outcome)
+            }
+        };
+
+        // Successful queries may report several steps, failures end them.
+        if last || outcome.is_err() {
+            self.count(query, outcome.is_ok());
+        }
+        if let Err(error) = outcome {
+            events.push(Event::QueryFailed { query, error });
+        }
+
+        events
+    }
+
+    /// Resolve a lookup with the first record passing verification, or fail it once the
+    /// query ends without one.
+    fn on_get_record(
+        &mut self,
+        query_id: kad::QueryId,
+        result: Result<kad::GetRecordOk, kad::GetRecordError>,
+        last: bool,
+    ) -> Option<Event> {
+        let destination = *self.lookups.get(&query_id)?;
+
+        if let Ok(kad::GetRecordOk::FoundRecord(peer_record)) = result {
+            let verified = AddressRecord::verify(&peer_record.record.value).and_then(|record| {
+                record.verify_membership(self.network.as_ref())?;
+                Ok(record)
+            });
+            match verified {
+                Ok(record) if record.address == destination => {
+                    self.lookups.remove(&query_id);
+                    return Some(Event::Resolved {
+                        destination,
+                        record,
+                    });
+                }
+                Ok(record) => warn!(
+                    "Discarding record for {} under key of {}",
+                    record.address, destination
+                ),
+                Err(e) => warn!("Discarding address-record for {}, {e}", destination),
+            }
+
+            if !last {
+                return None;
+            }
+        }
+
+        self.lookups.remove(&query_id);
+        Some(Event::Unresolved { destination })
+    }
+}
+
+/// Warn about a configuration which lets the records expire before they are republished.
+fn check(config: &Kademlia) {
+    if config.republish_interval >= config.record_ttl {
+        warn!("Records expire before they are republished, lower kademlia.republish_interval");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::pnet::PreSharedKey;
+
+    fn record(keypair: &Keypair, network: Option<&NetworkKey>) -> kad::Record {
+        let peer_id = keypair.public().to_peer_id();
+        let address = "10.1.2.3".parse().unwrap();
+        let record = AddressRecord {
+            address,
+            peer_id,
+            addresses: vec![],
+            membership_proof: network.map(|network| network.record_proof(&peer_id)),
+        };
+        kad::Record::new(record_key(&address), record.sign(keypair).unwrap())
+    }
+
+    #[test]
+    fn signed_record_is_valid() {
+        let keypair = Keypair::generate_ed25519();
+        let network = NetworkKey::new(PreSharedKey::new([7; 32]));
+
+        assert!(validate(&record(&keypair, None), None).is_ok());
+        assert!(validate(&record(&keypair, Some(&network)), Some(&network)).is_ok());
+    }
+
+    #[test]
+    fn record_without_membership_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let network = NetworkKey::new(PreSharedKey::new([7; 32]));
+        let other = NetworkKey::new(PreSharedKey::new([8; 32]));
+
+        for record in [record(&keypair, None), record(&keypair, Some(&other))] {
+            assert!(matches!(
+                validate(&record, Some(&network)),
+                Err(Error::NotAMember(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn record_under_other_key_is_rejected() {
+        let mut record = record(&Keypair::generate_ed25519(), None);
+        record.key = record_key(&"10.1.2.4".parse().unwrap());
+
+        assert!(matches!(validate(&record, None), Err(Error::KeyMismatch)));
+    }
+
+    #[test]
+    fn public_key_is_valid_under_its_own_key_only() {
+        let keypair = Keypair::generate_ed25519();
+        let value = keypair.public().encode_protobuf();
+
+        let record = kad::Record::new(pk_record_key(&keypair.public().to_peer_id()), value.clone());
+        assert!(validate(&record, None).is_ok());
+
+        let other = Keypair::generate_ed25519().public().to_peer_id();
+        let record = kad::Record::new(pk_record_key(&other), value);
+        assert!(matches!(validate(&record, None), Err(Error::KeyMismatch)));
+    }
+}
@@ -0,0 +1,308 @@
+use ipnet::IpNet;
+use libp2p::{Multiaddr, PeerId, core::SignedEnvelope, gossipsub, identity::Keypair};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{error, fmt};
+use tracing::{debug, info, warn};
+
+use crate::config::gossip::Gossip as Config;
+
+/// Domain-separation of the signature on announcements.
+const DOMAIN: &str = "vpnr-announcement";
+const PAYLOAD_TYPE: &[u8] = b"/vpnr/announcement/1";
+
+/// Delay before announcing, so changes in a burst are announced once.
+const PUBLISH_DELAY: Duration = Duration::from_secs(2);
+
+/// Members are dropped after missing this many announcements.
+const MISSED_ANNOUNCEMENTS: u32 = 3;
+
+/// The topic the members of `network_id` announce themselves on.
+pub fn topic(network_id: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("/vpnr/{network_id}/announce/1"))
+}
+
+/// A member describing itself to the network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub peer_id: PeerId,
+    /// Milliseconds since the UNIX-epoch, a newer announcement replaces older ones.
+    pub timestamp: u64,
+    /// The overlay-addresses of the member.
+    pub addresses: Vec<IpAddr>,
+    /// Subnets reachable through the member.
+    pub routes: Vec<IpNet>,
+    /// Addresses to dial the member on.
+    pub listen_addrs: Vec<Multiaddr>,
+    pub name: Option<String>,
+    pub capabilities: BTreeSet<String>,
+    /// The member is leaving the network.
+    #[serde(default)]
+    pub leaving: bool,
+}
+
+/// Error reading an announcement
+#[derive(Debug)]
+pub enum Error {
+    /// The signed envelope is malformed or its signature invalid.
+    Envelope(String),
+    /// The payload can not be decoded.
+    Malformed(serde_json::Error),
+    /// The announcement was signed by another peer than the one it names.
+    ForeignSigner(PeerId),
+    /// The announcement was published by another peer than the one it names.
+    ForeignSource(Option<PeerId>),
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Malformed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Envelope(e) => write!(f, "Invalid envelope: {e}"),
+            Error::Malformed(e) => write!(f, "Malformed announcement: {e}"),
+            Error::ForeignSigner(signer) => write!(f, "Announcement signed by {signer}"),
+            Error::ForeignSource(Some(source)) => write!(f, "Announcement published by {source}"),
+            Error::ForeignSource(None) => write!(f, "Announcement without source"),
+        }
+    }
+}
+
+impl Announcement {
+    /// Encode the announcement in an envelope signed by `keypair`.
+    pub fn sign(&self, keypair: &Keypair) -> Result<Vec<u8>, Error> {
+        let payload = serde_json::to_vec(self).map_err(Error::Malformed)?;
+        let envelope =
+            SignedEnvelope::new(keypair, DOMAIN.to_string(), PAYLOAD_TYPE.to_vec(), payload)
+                .map_err(|e| Error::Envelope(e.to_string()))?;
+
+        Ok(envelope.into_protobuf_encoding())
+    }
+
+    /// Decode an announcement, checking it is signed by the peer it names.
+    pub fn verify(bytes: &[u8]) -> Result<Self, Error> {
+        let envelope = SignedEnvelope::from_protobuf_encoding(bytes)
+            .map_err(|e| Error::Envelope(e.to_string()))?;
+        let (payload, signer) = envelope
+            .payload_and_signing_key(DOMAIN.to_string(), PAYLOAD_TYPE)
+            .map_err(|e| Error::Envelope(e.to_string()))?;
+
+        let announcement: Announcement =
+            serde_json::from_slice(payload).map_err(Error::Malformed)?;
+        if signer.to_peer_id() != announcement.peer_id {
+            return Err(Error::ForeignSigner(signer.to_peer_id()));
+        }
+
+        Ok(announcement)
+    }
+}
+
+/// A member of the network, as last announced.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub announcement: Announcement,
+    pub last_seen: Instant,
+}
+
+/// Announces this node on the network's topic and keeps the view of all members from their
+/// announcements.
+pub struct Gossip {
+    keypair: Keypair,
+    config: Config,
+    topic: gossipsub::IdentTopic,
+    capabilities: BTreeSet<String>,
+    /// Next announcement, `None` until the local addresses are known.
+    publish_at: Option<Instant>,
+    members: HashMap<PeerId, Member>,
+}
+
+impl Gossip {
+    pub fn new(
+        keypair: Keypair,
+        network_id: &str,
+        config: Config,
+        capabilities: BTreeSet<String>,
+    ) -> Self {
+        Self {
+            keypair,
+            config,
+            topic: topic(network_id),
+            capabilities,
+            publish_at: None,
+            members: HashMap::new(),
+        }
+    }
+
+    /// Apply a reloaded configuration, announcing the change.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        if self.publish_at.is_some() {
+            self.schedule_publish();
+        }
+    }
+
+    /// The members heard of, excluding this node.
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values()
+    }
+
+    pub fn member(&self, peer_id: &PeerId) -> Option<&Member> {
+        self.members.get(peer_id)
+    }
+
+    /// The member owning `address`, or routing it by longest-prefix match.
+    pub fn resolve(&self, address: IpAddr) -> Option<&Announcement> {
+        let announcements = self.members.values().map(|member| &member.announcement);
+
+        announcements
+            .clone()
+            .find(|announcement| announcement.addresses.contains(&address))
+            .or_else(|| {
+                announcements
+                    .filter_map(|announcement| {
+                        let route = announcement
+                            .routes
+                            .iter()
+                            .filter(|route| route.contains(&address))
+                            .max_by_key(|route| route.prefix_len())?;
+                        Some((route.prefix_len(), announcement))
+                    })
+                    .max_by_key(|(prefix_len, _)| *prefix_len)
+                    .map(|(_, announcement)| announcement)
+            })
+    }
+
+    /// Announce this node once the local addresses settled.
+    pub fn schedule_publish(&mut self) {
+        self.publish_at = Some(Instant::now() + PUBLISH_DELAY);
+    }
+
+    /// Announce this node when due and drop the members which stopped announcing.
+    pub fn maintain(
+        &mut self,
+        gossipsub: &mut gossipsub::Behaviour,
+        addresses: &[IpAddr],
+        routes: &[IpNet],
+        listen_addrs: Vec<Multiaddr>,
+    ) {
+        let now = Instant::now();
+
+        if self.publish_at.is_some_and(|at| at <= now) {
+            self.publish_at = Some(now + self.config.interval);
+            self.publish(gossipsub, addresses, routes, listen_addrs, false);
+        }
+
+        let ttl = self.config.interval * MISSED_ANNOUNCEMENTS;
+        self.members.retain(|peer_id, member| {
+            let alive = now.duration_since(member.last_seen) < ttl;
+            if !alive {
+                info!("Member {} left the network", peer_id);
+            }
+            alive
+        });
+    }
+
+    /// Announce this node leaves the network, so the members drop it from their view.
+    pub fn leave(&mut self, gossipsub: &mut gossipsub::Behaviour) {
+        self.publish_at = None;
+        self.publish(gossipsub, &[], &[], vec![], true);
+    }
+
+    fn publish(
+        &mut self,
+        gossipsub: &mut gossipsub::Behaviour,
+        addresses: &[IpAddr],
+        routes: &[IpNet],
+        listen_addrs: Vec<Multiaddr>,
+        leaving: bool,
+    ) {
+        let announcement = Announcement {
+            peer_id: self.keypair.public().to_peer_id(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            addresses: addresses.to_vec(),
+            routes: routes.to_vec(),
+            listen_addrs,
+            name: self.config.name.clone(),
+            capabilities: self.capabilities.clone(),
+            leaving,
+        };
+
+        let data = match announcement.sign(&self.keypair) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Error signing announcement, {e}");
+                return;
+            }
+        };
+
+        // Fails without subscribed peers, the next interval retries.
+        match gossipsub.publish(self.topic.clone(), data) {
+            Ok(_) => debug!("Announced on {}", self.topic),
+            Err(e) => debug!("Error announcing on {}, {e}", self.topic),
+        }
+    }
+
+    /// Judge a message on the topic, forwarded by a peer which is a `member` or not. Valid
+    /// announcements update the view and are forwarded.
+    pub fn on_message(
+        &mut self,
+        message: &gossipsub::Message,
+        member: bool,
+    ) -> gossipsub::MessageAcceptance {
+        if message.topic != self.topic.hash() {
+            return gossipsub::MessageAcceptance::Ignore;
+        }
+        if !member {
+            debug!("Rejecting announcement forwarded by a non-member");
+            return gossipsub::MessageAcceptance::Reject;
+        }
+
+        let announcement = match Announcement::verify(&message.data).and_then(|announcement| {
+            match message.source == Some(announcement.peer_id) {
+                true => Ok(announcement),
+                false => Err(Error::ForeignSource(message.source)),
+            }
+        }) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                warn!("Rejecting announcement, {e}");
+                return gossipsub::MessageAcceptance::Reject;
+            }
+        };
+
+        let peer_id = announcement.peer_id;
+        match self.members.get(&peer_id) {
+            Some(known) if known.announcement.timestamp >= announcement.timestamp => {
+                return gossipsub::MessageAcceptance::Ignore;
+            }
+            _ if announcement.leaving => {
+                info!("Member {} left the network", peer_id);
+                self.members.remove(&peer_id);
+                return gossipsub::MessageAcceptance::Accept;
+            }
+            Some(_) => debug!("Member {} announced {:?}", peer_id, announcement),
+            None => info!("Member {} joined the network, {:?}", peer_id, announcement),
+        }
+
+        self.members.insert(
+            peer_id,
+            Member {
+                announcement,
+                last_seen: Instant::now(),
+            },
+        );
+        gossipsub::MessageAcceptance::Accept
+    }
+}
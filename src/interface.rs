@@ -0,0 +1,71 @@
+use ipnet::IpNet;
+use std::{error::Error, io, net::IpAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::info;
+use tun::{AbstractDevice, AsyncDevice};
+
+/// The local TUN device, carrying the overlay-traffic between the kernel and the swarm.
+pub struct Interface {
+    device: AsyncDevice,
+    prefix_len: u8,
+    buffer: Vec<u8>,
+}
+
+/// Create the TUN device `name`, assign `ip_addr/prefix_len` to it and bring it up.
+pub fn create(
+    name: &str,
+    ip_addr: IpAddr,
+    prefix_len: u8,
+    mtu: u16,
+) -> Result<Interface, Box<dyn Error>> {
+    let network = IpNet::new(ip_addr, prefix_len)?;
+
+    let mut config = tun::Configuration::default();
+    config
+        .tun_name(name)
+        .address(ip_addr)
+        .netmask(network.netmask())
+        .mtu(mtu)
+        .up();
+
+    let device = tun::create_as_async(&config)?;
+    info!("Created interface {} with address {}", name, network);
+
+    Ok(Interface {
+        device,
+        prefix_len,
+        buffer: vec![0u8; usize::from(mtu)],
+    })
+}
+
+impl Interface {
+    /// Replace the overlay-address of the interface, keeping its prefix-length.
+    pub fn set_address(&mut self, ip_addr: IpAddr) -> Result<(), Box<dyn Error>> {
+        let network = IpNet::new(ip_addr, self.prefix_len)?;
+
+        self.device.set_address(ip_addr)?;
+        self.device.set_netmask(network.netmask())?;
+        info!("Changed interface address to {}", network);
+
+        Ok(())
+    }
+
+    /// Read the next IP-packet the kernel routed onto the interface.
+    pub async fn read(&mut self) -> Result<Vec<u8>, io::Error> {
+        let size = self.device.read(&mut self.buffer).await?;
+
+        Ok(self.buffer[..size].to_vec())
+    }
+
+    /// Remove the device, with its address and routes.
+    pub fn close(self) {
+        // The device is not persistent, the kernel removes it once the last descriptor closes.
+        drop(self.device);
+        info!("Removed interface");
+    }
+
+    /// Hand a received IP-packet to the kernel.
+    pub async fn write(&mut self, packet: &[u8]) -> Result<(), io::Error> {
+        self.device.write_all(packet).await
+    }
+}
@@ -0,0 +1,128 @@
+use libp2p::{PeerId, ping, swarm::ConnectionId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Weight of a new sample in the smoothed round-trip-time, as TCP's SRTT.
+const SMOOTHING: f64 = 0.125;
+
+/// The latency of a connection.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Latency {
+    /// The last round-trip-time measured.
+    pub rtt: Duration,
+    /// The round-trip-time, smoothed over the recent pings.
+    pub smoothed_rtt: Duration,
+    /// Whether the connection runs over a relay.
+    pub relayed: bool,
+}
+
+struct Entry {
+    peer_id: PeerId,
+    relayed: bool,
+    latency: Option<Latency>,
+    /// Consecutive failed pings.
+    failures: u32,
+}
+
+/// Round-trip-times of the connections, from the pings.
+pub struct Latencies {
+    max_failures: u32,
+    connections: HashMap<ConnectionId, Entry>,
+}
+
+impl Latencies {
+    pub fn new(max_failures: u32) -> Self {
+        Self {
+            max_failures,
+            connections: HashMap::new(),
+        }
+    }
+
+    pub fn set_max_failures(&mut self, max_failures: u32) {
+        self.max_failures = max_failures;
+    }
+
+    pub fn on_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, relayed: bool) {
+        self.connections.insert(
+            connection_id,
+            Entry {
+                peer_id,
+                relayed,
+                latency: None,
+                failures: 0,
+            },
+        );
+    }
+
+    pub fn on_closed(&mut self, connection_id: &ConnectionId) {
+        self.connections.remove(connection_id);
+    }
+
+    /// Record a ping, returning the connection to close once it failed too often.
+    pub fn on_ping(&mut self, event: ping::Event) -> Option<ConnectionId> {
+        let entry = self.connections.get_mut(&event.connection)?;
+
+        match event.result {
+            Ok(rtt) => {
+                entry.failures = 0;
+                let smoothed_rtt = match entry.latency {
+                    Some(latency) => {
+                        latency.smoothed_rtt.mul_f64(1.0 - SMOOTHING) + rtt.mul_f64(SMOOTHING)
+                    }
+                    None => rtt,
+                };
+                entry.latency = Some(Latency {
+                    rtt,
+                    smoothed_rtt,
+                    relayed: entry.relayed,
+                });
+                None
+            }
+            // Peers without ping are not measured, but not dropped either.
+            Err(ping::Failure::Unsupported) => None,
+            Err(e) => {
+                entry.failures += 1;
+                debug!(
+                    "Ping to {} failed {} times, {e}",
+                    entry.peer_id, entry.failures
+                );
+
+                match entry.failures >= self.max_failures {
+                    true => {
+                        warn!(
+                            "Closing connection to {} after {} failed pings",
+                            entry.peer_id, entry.failures
+                        );
+                        Some(event.connection)
+                    }
+                    false => None,
+                }
+            }
+        }
+    }
+
+    /// The latency of a connection, once measured.
+    pub fn get(&self, connection_id: &ConnectionId) -> Option<Latency> {
+        self.connections.get(connection_id)?.latency
+    }
+
+    /// The latency to each measured peer, over its fastest connection.
+    pub fn table(&self) -> BTreeMap<PeerId, Latency> {
+        let mut table = BTreeMap::new();
+        for entry in self.connections.values() {
+            if let Some(latency) = entry.latency {
+                table
+                    .entry(entry.peer_id)
+                    .and_modify(|fastest: &mut Latency| {
+                        if latency.smoothed_rtt < fastest.smoothed_rtt {
+                            *fastest = latency;
+                        }
+                    })
+                    .or_insert(latency);
+            }
+        }
+        table
+    }
+}
@@ -8,31 +8,37 @@ use libp2p::{
     allow_block_list::{self, BlockedPeers},
     autonat::v1 as autonat,
     connection_limits::{self},
-    dcutr, identify,
-    identity::Keypair,
-    kad, mdns, memory_connection_limits, ping,
+    dcutr, gossipsub, identify, kad, mdns, memory_connection_limits, ping,
     pnet::PreSharedKey,
     relay,
     swarm::{NetworkBehaviour, behaviour::toggle::Toggle},
     upnp,
 };
-use std::{
-    error::Error,
-    fs::OpenOptions,
-    io::Read,
-    num::NonZeroUsize,
-    path::PathBuf,
-    str::FromStr,
-    time::{Duration, Instant},
-};
+use std::{error::Error, path::Path, process::ExitCode, str::FromStr};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
+mod acl;
+mod autorelay;
 mod cli;
+mod client;
 pub mod config;
+mod control;
+mod dht;
+mod gossip;
+mod identity;
+mod interface;
+mod latency;
+mod metrics;
+mod network;
+mod relay_server;
+mod reload;
+mod rendezvous;
 mod swarm;
+#[cfg(test)]
+mod testing;
 mod vpn;
 
 #[derive(NetworkBehaviour)]
@@ -46,29 +52,24 @@ struct VpnBehaviour {
     identify: Toggle<identify::Behaviour>,
     ping: Toggle<ping::Behaviour>,
     kademlia: Toggle<kad::Behaviour<kad::store::MemoryStore>>,
+    gossipsub: Toggle<gossipsub::Behaviour>,
+    rendezvous: Toggle<libp2p::rendezvous::client::Behaviour>,
+    rendezvous_server: Toggle<libp2p::rendezvous::server::Behaviour>,
     mdns: Toggle<mdns::tokio::Behaviour>,
     relay: Toggle<relay::Behaviour>,
+    relay_client: relay::client::Behaviour,
     upnp: Toggle<upnp::tokio::Behaviour>,
 }
 
-fn read_keyfile(keyfile: PathBuf) -> Result<Vec<u8>, std::io::Error> {
-    let mut keyfile = OpenOptions::new().read(true).open(keyfile)?;
-
-    let mut bytes = vec![];
-    keyfile.read_to_end(&mut bytes)?;
-
-    Ok(bytes)
-}
-
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
     tracing_subscriber::fmt::init();
 
     let args = cli::CliArgs::parse();
 
     match args.command {
         Commands::GenerateKey { keyfile: _ } => {
-            let config = Config::from(args);
+            let config = Config::load(args)?;
 
             let data: [u8; 32] = rand::random();
             let psk = PreSharedKey::new(data);
@@ -78,14 +79,42 @@ async fn main() -> Result<(), Box<dyn Error>> {
             psk_file.write_all(format!("{psk}").as_bytes()).await?;
             psk_file.flush().await?;
 
-            Ok(())
+            Ok(ExitCode::SUCCESS)
+        }
+        Commands::Status { json, .. } => {
+            let mut client = connect(args).await?;
+            client::status(&mut client, json).await
+        }
+        Commands::Peers { json, .. } => {
+            let mut client = connect(args).await?;
+            client::peers(&mut client, json).await
+        }
+        Commands::Ping {
+            ref target,
+            count,
+            json,
+            ..
+        } => {
+            let target = target.clone();
+            let mut client = connect(args).await?;
+            client::ping(&mut client, &target, count, json).await
         }
         Commands::Start {
             ip_addr: _,
+            prefix_len: _,
+            network_prefix: _,
+            mtu: _,
+            routes: _,
             interface_name: _,
             listen_addr: _,
             listen_port: _,
             keyfile: _,
+            private_network: _,
+            identity_file: _,
+            identity_encoding: _,
+            control_socket: _,
+            enable_control_api: _,
+            metrics_addr: _,
             enable_dht: _,
             enable_mdns: _,
             enable_upnp: _,
@@ -93,63 +122,140 @@ async fn main() -> Result<(), Box<dyn Error>> {
             enable_dcutr: _,
             enable_autonat: _,
             enable_identify: _,
+            enable_gossipsub: _,
+            enable_rendezvous: _,
+            enable_rendezvous_server: _,
+            enable_auto_relay: _,
+            enable_ping: _,
         } => {
-            let config = Config::from(args);
+            let config = Config::load(args.clone())?;
             println!("{}", serde_yaml::to_string(&config)?);
 
-            // let _interface = match interface::create(config.interface_name, config.ip_addr) {
-            //     Ok(interface) => interface,
-            //     Err(e) => panic!("Error creating TUN: {}.", e),
-            // };
-
-            let _bytes = read_keyfile(PathBuf::from(config.clone().keyfile))?;
-
-            let local_keypair = Keypair::generate_ed25519();
+            let local_keypair = identity::load_or_generate(
+                Path::new(&config.identity_file),
+                config.identity_encoding,
+            )?;
 
             let local_peer_id = PeerId::from(local_keypair.public());
             println!("Local peer-id: {}", local_peer_id.clone());
 
-            let mut psk_file = File::open(config.keyfile.clone()).await?;
+            let ip_addr = config
+                .overlay_address(&local_peer_id)
+                .ok_or("The network-prefix leaves no room for host-addresses")?;
 
-            let mut psk = String::from("");
-            psk_file.read_to_string(&mut psk).await?;
+            let interface = interface::create(
+                &config.interface_name,
+                ip_addr,
+                config.overlay_prefix_len(),
+                config.mtu,
+            )
+            .map_err(|e| format!("Error creating TUN: {e}"))?;
 
-            let pre_shared_key = PreSharedKey::from_str(&psk)?;
+            let network = match config.is_private_network() {
+                true => {
+                    let mut psk_file = File::open(config.keyfile.clone()).await?;
 
-            println!(
-                "Pre-shared-key, fingerprint: {}",
-                pre_shared_key.fingerprint()
-            );
+                    let mut psk = String::from("");
+                    psk_file.read_to_string(&mut psk).await?;
+
+                    let pre_shared_key = PreSharedKey::from_str(&psk)?;
+
+                    println!(
+                        "Pre-shared-key, fingerprint: {}",
+                        pre_shared_key.fingerprint()
+                    );
+
+                    Some(network::NetworkKey::new(pre_shared_key))
+                }
+                false => {
+                    println!("Not using a pre-shared key, the network is public ...");
+                    None
+                }
+            };
+
+            let network_id = config.network_id(network.as_ref());
+            let capabilities = swarm::capabilities(&config);
 
-            let mut swarm = match swarm::build(&local_keypair, pre_shared_key, config.clone()) {
-                Ok(swarm) => swarm,
-                Err(e) => {
-                    panic!("Error building swarm, {e}")
+            let mut acl = acl::Acl::load(config.acl.clone())?;
+
+            let metrics = metrics::Metrics::default();
+            let p2p_metrics = match config.metrics_addr {
+                Some(address) => {
+                    let mut registry = libp2p::metrics::Registry::default();
+                    let p2p_metrics = libp2p::metrics::Metrics::new(&mut registry);
+                    metrics.register(&mut registry);
+                    metrics::serve(address, registry).await?;
+                    Some(p2p_metrics)
+                }
+                None => {
+                    println!("Not serving metrics ...");
+                    None
                 }
             };
 
-            let mut pk_record_key = vec![];
-            pk_record_key.extend_from_slice("/pk/".as_bytes());
-            pk_record_key.extend_from_slice(swarm.local_peer_id().to_bytes().as_slice());
-
-            let mut pk_record =
-                kad::Record::new(pk_record_key, local_keypair.public().encode_protobuf());
-            pk_record.publisher = Some(*swarm.local_peer_id());
-            pk_record.expires = Instant::now().checked_add(Duration::from_secs(60));
-
-            if swarm.behaviour().kademlia.is_enabled() {
-                swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .as_mut()
-                    .unwrap()
-                    .put_record(
-                        pk_record.clone(),
-                        kad::Quorum::N(NonZeroUsize::new(1).unwrap()),
-                    )?;
+            let relayed = relay_server::Relayed::default();
+            let mut swarm = swarm::build(
+                &local_keypair,
+                network.clone(),
+                config.clone(),
+                metrics.clone(),
+                relayed.clone(),
+            )
+            .map_err(|e| format!("Error building swarm, {e}"))?;
+
+            acl.apply(&mut swarm);
+
+            let gossip = gossip::Gossip::new(
+                local_keypair.clone(),
+                &network_id,
+                config.gossip.clone(),
+                capabilities,
+            );
+            let rendezvous = rendezvous::Rendezvous::new(
+                &network_id,
+                config.discovery.rendezvous,
+                config.rendezvous.clone(),
+            )?;
+            let autorelay =
+                autorelay::AutoRelay::new(config.discovery.auto_relay, config.relay_client.clone());
+            let dht = dht::Dht::new(
+                local_keypair,
+                config.kademlia.clone(),
+                network.clone(),
+                metrics.clone(),
+            );
+
+            let control = control::Control::bind(config.control_socket.as_deref())?;
+
+            let node = swarm::Node {
+                interface,
+                dht,
+                gossip,
+                rendezvous,
+                autorelay,
+                relay_server: relay_server::RelayServer::new(relayed),
+                latencies: latency::Latencies::new(config.ping.max_failures),
+                control,
+                acl,
+                metrics,
+                p2p_metrics,
+                network,
+                config: config.clone(),
+                started: config,
+                args,
             };
 
-            swarm::run(swarm).await
+            Ok(swarm::run(swarm, node).await?.into())
         }
     }
 }
+
+/// Connect to the control-API of the running node.
+async fn connect(args: cli::CliArgs) -> Result<client::Client, Box<dyn Error>> {
+    let config = Config::load(args)?;
+    let path = config
+        .control_socket
+        .ok_or("The control-API is disabled, no control_socket configured")?;
+
+    client::Client::connect(&path).await
+}
@@ -0,0 +1,307 @@
+use libp2p::{
+    PeerId,
+    metrics::{Recorder, Registry},
+    swarm::SwarmEvent,
+};
+use prometheus_client::{
+    encoding::{EncodeLabelSet, EncodeLabelValue, text::encode},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::VpnBehaviourEvent;
+use crate::dht::Query;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Time a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes read of a request, its line and headers.
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelValue)]
+enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct PeerLabels {
+    peer: String,
+    direction: Direction,
+}
+
+/// Why a packet was dropped instead of delivered.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum DropReason {
+    /// Read from the interface, but not an IP-packet.
+    NotIp,
+    /// The queue of its unrouted destination was full.
+    QueueFull,
+    /// The queue of the connection to its peer was full.
+    HandlerQueueFull,
+    /// Larger than a VPN-frame carries.
+    TooLarge,
+    /// Its destination is no unicast-address, or outside the overlay and every route.
+    Unroutable,
+    /// Its destination was not resolved, or not reached in time.
+    Unresolved,
+    /// The peer routing its destination is not connected.
+    NotConnected,
+    /// Received from a peer not allowed to send from its source-address.
+    SourceNotAllowed,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DropLabels {
+    reason: DropReason,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EncodeLabelValue)]
+enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct QueryLabels {
+    query: Query,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum TunOperation {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct TunLabels {
+    operation: TunOperation,
+}
+
+/// The vpnr-specific metrics. Clones share the values, unless registered they are not
+/// exported.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    packets: Family<PeerLabels, Counter>,
+    bytes: Family<PeerLabels, Counter>,
+    dropped: Family<DropLabels, Counter>,
+    tunnels: Gauge,
+    handshake_failures: Counter,
+    tun_errors: Family<TunLabels, Counter>,
+    dht_queries: Family<QueryLabels, Counter>,
+    dht_rejected_records: Counter,
+}
+
+impl Metrics {
+    /// Export the metrics through `registry`, prefixed with `vpnr`.
+    pub fn register(&self, registry: &mut Registry) {
+        let registry = registry.sub_registry_with_prefix("vpnr");
+        registry.register(
+            "peer_packets",
+            "Packets exchanged with a peer through the tunnel",
+            self.packets.clone(),
+        );
+        registry.register(
+            "peer_bytes",
+            "Bytes exchanged with a peer through the tunnel",
+            self.bytes.clone(),
+        );
+        registry.register(
+            "dropped_packets",
+            "Packets dropped instead of delivered",
+            self.dropped.clone(),
+        );
+        registry.register(
+            "tunnels",
+            "Peers with an established VPN-stream",
+            self.tunnels.clone(),
+        );
+        registry.register(
+            "handshake_failures",
+            "Failed VPN-handshakes",
+            self.handshake_failures.clone(),
+        );
+        registry.register(
+            "tun_errors",
+            "Errors reading from and writing to the interface",
+            self.tun_errors.clone(),
+        );
+        registry.register(
+            "dht_queries",
+            "Finished DHT-queries, by kind and outcome",
+            self.dht_queries.clone(),
+        );
+        registry.register(
+            "dht_rejected_records",
+            "Records of other nodes the DHT did not store",
+            self.dht_rejected_records.clone(),
+        );
+    }
+
+    pub fn received(&self, peer_id: &PeerId, bytes: usize) {
+        self.count(peer_id, Direction::In, 1, bytes as u64);
+    }
+
+    pub fn sent(&self, peer_id: &PeerId, packets: u64, bytes: u64) {
+        self.count(peer_id, Direction::Out, packets, bytes);
+    }
+
+    fn count(&self, peer_id: &PeerId, direction: Direction, packets: u64, bytes: u64) {
+        let labels = PeerLabels {
+            peer: peer_id.to_string(),
+            direction,
+        };
+        self.packets.get_or_create(&labels).inc_by(packets);
+        self.bytes.get_or_create(&labels).inc_by(bytes);
+    }
+
+    /// Stop exporting the traffic of the disconnected `peer_id`.
+    pub fn remove_peer(&self, peer_id: &PeerId) {
+        for direction in [Direction::In, Direction::Out] {
+            let labels = PeerLabels {
+                peer: peer_id.to_string(),
+                direction,
+            };
+            self.packets.remove(&labels);
+            self.bytes.remove(&labels);
+        }
+    }
+
+    pub fn dropped(&self, reason: DropReason, packets: usize) {
+        self.dropped
+            .get_or_create(&DropLabels { reason })
+            .inc_by(packets as u64);
+    }
+
+    pub fn set_tunnels(&self, tunnels: usize) {
+        self.tunnels.set(tunnels as i64);
+    }
+
+    pub fn handshake_failed(&self) {
+        self.handshake_failures.inc();
+    }
+
+    pub fn tun_error(&self, operation: TunOperation) {
+        self.tun_errors
+            .get_or_create(&TunLabels { operation })
+            .inc();
+    }
+
+    pub fn dht_query(&self, query: Query, succeeded: bool) {
+        let outcome = match succeeded {
+            true => Outcome::Succeeded,
+            false => Outcome::Failed,
+        };
+        self.dht_queries
+            .get_or_create(&QueryLabels { query, outcome })
+            .inc();
+    }
+
+    pub fn dht_rejected_record(&self) {
+        self.dht_rejected_records.inc();
+    }
+}
+
+/// Record `event` to the libp2p-metrics, of the swarm and the behaviours that have any.
+pub fn record(metrics: &libp2p::metrics::Metrics, event: &SwarmEvent<VpnBehaviourEvent>) {
+    match event {
+        SwarmEvent::Behaviour(VpnBehaviourEvent::Kademlia(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(VpnBehaviourEvent::Identify(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(VpnBehaviourEvent::Relay(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(VpnBehaviourEvent::Dcutr(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(VpnBehaviourEvent::Ping(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(VpnBehaviourEvent::Gossipsub(event)) => metrics.record(event),
+        _ => {}
+    }
+    metrics.record(event);
+}
+
+/// Serve the metrics of `registry` to Prometheus, on `GET /metrics` of `address`.
+pub async fn serve(address: SocketAddr, registry: Registry) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let registry = Arc::new(registry);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let registry = registry.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, &registry).await {
+                            warn!("Error serving metrics, {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Error accepting metrics-connection, {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Answer a single HTTP-request, then close the connection.
+async fn respond(stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let mut request = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        reader.read_line(&mut request).await?;
+        // The headers are of no interest, but have to be read before answering.
+        let mut header = String::new();
+        while reader.read_line(&mut header).await? > 2 {
+            header.clear();
+        }
+        Ok::<_, io::Error>(())
+    })
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+    // Whatever filled the limit was cut off.
+    let exceeded = reader.get_ref().limit() == 0;
+
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        _ if exceeded => (
+            "431 Request Header Fields Too Large",
+            "text/plain",
+            String::from("Request too large\n"),
+        ),
+        (Some("GET"), Some("/metrics")) => {
+            let mut body = String::new();
+            match encode(&mut body, registry) {
+                Ok(()) => ("200 OK", CONTENT_TYPE, body),
+                Err(_) => (
+                    "500 Internal Server Error",
+                    "text/plain",
+                    String::from("Error encoding metrics\n"),
+                ),
+            }
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", String::from("Not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("Method not allowed\n"),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let mut stream = reader.into_inner().into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,132 @@
+use hmac::{Hmac, Mac};
+use libp2p::{
+    PeerId,
+    pnet::{Fingerprint, PreSharedKey},
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Domain-separator for deriving secrets from the pre-shared key.
+const SECRET_DOMAIN: &[u8] = b"vpnr network secret";
+
+/// The pre-shared key of a private network and the secrets derived from it.
+#[derive(Clone)]
+pub struct NetworkKey {
+    psk: PreSharedKey,
+    secret: [u8; 32],
+}
+
+impl NetworkKey {
+    pub fn new(psk: PreSharedKey) -> Self {
+        // `PreSharedKey` does not expose its bytes, its canonical encoding holds them.
+        let secret = Sha256::new()
+            .chain_update(SECRET_DOMAIN)
+            .chain_update(psk.to_string())
+            .finalize()
+            .into();
+
+        Self { psk, secret }
+    }
+
+    pub fn psk(&self) -> PreSharedKey {
+        self.psk
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.psk.fingerprint()
+    }
+
+    /// Public identifier of the network, a hash of the key's fingerprint.
+    pub fn network_id(&self) -> String {
+        let hash = Sha256::new()
+            .chain_update(b"vpnr network id")
+            .chain_update(self.fingerprint().to_string())
+            .finalize();
+
+        hash[..8].iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Key of the TCP-transport between members.
+    pub fn transport_key(&self) -> [u8; 32] {
+        self.mac(&[b"transport"]).finalize().into_bytes().into()
+    }
+
+    /// Pre-shared key mixed into the WireGuard-sessions between members.
+    pub fn wireguard_psk(&self) -> [u8; 32] {
+        self.mac(&[b"wireguard"]).finalize().into_bytes().into()
+    }
+
+    /// Proof that `local` knows the network's key, bound to the connection with `remote`.
+    pub fn membership_proof(&self, local: &PeerId, remote: &PeerId) -> [u8; 32] {
+        self.membership_mac(local, remote)
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// Check a proof `remote` sent to `local`, in constant time.
+    pub fn verify_membership(&self, proof: &[u8], remote: &PeerId, local: &PeerId) -> bool {
+        self.membership_mac(remote, local)
+            .verify_slice(proof)
+            .is_ok()
+    }
+
+    /// Proof that `publisher` knows the network's key, carried in its signed DHT-records.
+    pub fn record_proof(&self, publisher: &PeerId) -> [u8; 32] {
+        self.mac(&[b"record", &publisher.to_bytes()])
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// Check the proof in a record signed by `publisher`, in constant time.
+    pub fn verify_record(&self, proof: &[u8], publisher: &PeerId) -> bool {
+        self.mac(&[b"record", &publisher.to_bytes()])
+            .verify_slice(proof)
+            .is_ok()
+    }
+
+    fn membership_mac(&self, prover: &PeerId, verifier: &PeerId) -> HmacSha256 {
+        self.mac(&[b"membership", &prover.to_bytes(), &verifier.to_bytes()])
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(&(part.len() as u32).to_be_bytes());
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+impl fmt::Debug for NetworkKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetworkKey")
+            .field("fingerprint", &self.fingerprint())
+            .finish()
+    }
+}
+
+/// The peers which proved their membership, shared with the behaviours admitting only members.
+#[derive(Debug, Clone, Default)]
+pub struct Members(Arc<RwLock<HashSet<PeerId>>>);
+
+impl Members {
+    pub fn insert(&self, peer_id: PeerId) {
+        self.0.write().unwrap().insert(peer_id);
+    }
+
+    pub fn remove(&self, peer_id: &PeerId) {
+        self.0.write().unwrap().remove(peer_id);
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.0.read().unwrap().contains(peer_id)
+    }
+}
@@ -0,0 +1,340 @@
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    Multiaddr, PeerId,
+    core::muxing::{StreamMuxer, StreamMuxerBox, StreamMuxerEvent, StreamMuxerExt, SubstreamBox},
+    relay,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::network::Members;
+
+const HOP_PROTOCOL: &[u8] = b"/libp2p/circuit/relay/0.2.0/hop";
+const STOP_PROTOCOL: &[u8] = b"/libp2p/circuit/relay/0.2.0/stop";
+
+/// Bytes of a stream searched for the protocol it negotiates.
+const SNIFF_LEN: usize = 64;
+
+/// Admits only members to reservations and circuits of the relay.
+pub struct MembersOnly(pub Members);
+
+impl relay::RateLimiter for MembersOnly {
+    fn try_next(&mut self, peer: PeerId, _addr: &Multiaddr, _now: Instant) -> bool {
+        let member = self.0.contains(&peer);
+        if !member {
+            debug!("Not relaying for {}, not a member", peer);
+        }
+        member
+    }
+}
+
+/// What the relay did for a peer.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PeerStats {
+    pub reservations: u64,
+    pub reservations_denied: u64,
+    /// Circuits the peer opened, as source.
+    pub circuits: u64,
+    pub circuits_denied: u64,
+    /// Circuits to or from the peer which are still open.
+    pub active_circuits: u64,
+    /// Bytes relayed to and from the peer, of its circuits in both roles.
+    pub bytes: u64,
+}
+
+/// Bytes relayed for each peer, shared with the connections counting them.
+#[derive(Debug, Default, Clone)]
+pub struct Relayed(Arc<Mutex<HashMap<PeerId, u64>>>);
+
+impl Relayed {
+    fn add(&self, peer_id: &PeerId, bytes: usize) {
+        *self.0.lock().unwrap().entry(*peer_id).or_default() += bytes as u64;
+    }
+}
+
+/// Statistics of the relay-server, by peer.
+#[derive(Debug, Default)]
+pub struct RelayServer {
+    stats: HashMap<PeerId, PeerStats>,
+    relayed: Relayed,
+}
+
+impl RelayServer {
+    /// Statistics reporting the bytes counted by the connections sharing `relayed`.
+    pub fn new(relayed: Relayed) -> Self {
+        Self {
+            stats: HashMap::new(),
+            relayed,
+        }
+    }
+
+    pub fn stats(&self) -> HashMap<PeerId, PeerStats> {
+        let mut stats = self.stats.clone();
+        for (peer_id, bytes) in self.relayed.0.lock().unwrap().iter() {
+            stats.entry(*peer_id).or_default().bytes = *bytes;
+        }
+        stats
+    }
+
+    pub fn on_event(&mut self, event: relay::Event) {
+        match event {
+            relay::Event::ReservationReqAccepted {
+                src_peer_id,
+                renewed,
+            } => {
+                if !renewed {
+                    info!("Relaying for {}", src_peer_id);
+                }
+                self.stats.entry(src_peer_id).or_default().reservations += 1;
+            }
+            relay::Event::ReservationReqDenied { src_peer_id } => {
+                self.stats
+                    .entry(src_peer_id)
+                    .or_default()
+                    .reservations_denied += 1;
+            }
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => {
+                debug!("Relaying circuit from {} to {}", src_peer_id, dst_peer_id);
+                let src = self.stats.entry(src_peer_id).or_default();
+                src.circuits += 1;
+                src.active_circuits += 1;
+                self.stats.entry(dst_peer_id).or_default().active_circuits += 1;
+            }
+            relay::Event::CircuitReqDenied { src_peer_id, .. } => {
+                self.stats.entry(src_peer_id).or_default().circuits_denied += 1;
+            }
+            relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                ..
+            } => {
+                for peer_id in [src_peer_id, dst_peer_id] {
+                    let stats = self.stats.entry(peer_id).or_default();
+                    stats.active_circuits = stats.active_circuits.saturating_sub(1);
+                }
+            }
+            event => debug!("{:?}", event),
+        }
+    }
+}
+
+/// Muxer of a connection counting the bytes relayed for its peer. libp2p's relay does not
+/// report them, so the streams are matched by the protocol they negotiate: the circuits the
+/// peer opens through us, and those we open to it.
+pub struct Counting {
+    inner: StreamMuxerBox,
+    peer_id: PeerId,
+    relayed: Relayed,
+}
+
+impl Counting {
+    pub fn new<M>(peer_id: PeerId, muxer: M, relayed: Relayed) -> Self
+    where
+        M: StreamMuxer + Send + 'static,
+        M::Substream: Send + 'static,
+        M::Error: Send + Sync + 'static,
+    {
+        Self {
+            inner: StreamMuxerBox::new(muxer),
+            peer_id,
+            relayed,
+        }
+    }
+
+    fn substream(&self, inner: SubstreamBox, inbound: bool) -> Substream {
+        Substream::new(inner, inbound, self.peer_id, self.relayed.clone())
+    }
+}
+
+impl StreamMuxer for Counting {
+    type Substream = Substream;
+    type Error = io::Error;
+
+    fn poll_inbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let inner = ready!(self.inner.poll_inbound_unpin(cx))?;
+        Poll::Ready(Ok(self.substream(inner, true)))
+    }
+
+    fn poll_outbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let inner = ready!(self.inner.poll_outbound_unpin(cx))?;
+        Poll::Ready(Ok(self.substream(inner, false)))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+enum State {
+    /// Searching the bytes the remote proposes on inbound streams, or we propose on outbound
+    /// ones, for the protocol of a relayed circuit.
+    Sniffing {
+        protocol: &'static [u8],
+        seen: Vec<u8>,
+    },
+    Relayed,
+    Other,
+}
+
+pub struct Substream {
+    inner: SubstreamBox,
+    inbound: bool,
+    state: State,
+    peer_id: PeerId,
+    relayed: Relayed,
+}
+
+impl Substream {
+    fn new(inner: SubstreamBox, inbound: bool, peer_id: PeerId, relayed: Relayed) -> Self {
+        Self {
+            inner,
+            inbound,
+            state: State::Sniffing {
+                protocol: match inbound {
+                    true => HOP_PROTOCOL,
+                    false => STOP_PROTOCOL,
+                },
+                seen: vec![],
+            },
+            peer_id,
+            relayed,
+        }
+    }
+
+    /// Count `bytes` if the stream is relayed, `proposed` tells whether they carry the
+    /// protocol of the stream.
+    fn observe(&mut self, bytes: &[u8], proposed: bool) {
+        let next = match &mut self.state {
+            State::Relayed => {
+                self.relayed.add(&self.peer_id, bytes.len());
+                return;
+            }
+            State::Sniffing { protocol, seen } if proposed => {
+                let take = bytes.len().min(SNIFF_LEN - seen.len());
+                seen.extend_from_slice(&bytes[..take]);
+                if seen
+                    .windows(protocol.len())
+                    .any(|window| window == *protocol)
+                {
+                    State::Relayed
+                } else if seen.len() >= SNIFF_LEN {
+                    State::Other
+                } else {
+                    return;
+                }
+            }
+            _ => return,
+        };
+        self.state = next;
+    }
+}
+
+impl AsyncRead for Substream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let proposed = self.inbound;
+        self.observe(&buf[..read], proposed);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncWrite for Substream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        let proposed = !self.inbound;
+        self.observe(&buf[..written], proposed);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::AsyncReadExt;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use libp2p::identity::Keypair;
+
+    /// A stream of `peer_id` reading `wire`.
+    fn substream(peer_id: PeerId, relayed: &Relayed, wire: Vec<u8>, inbound: bool) -> Substream {
+        let inner = SubstreamBox::new(Cursor::new(wire));
+        Substream::new(inner, inbound, peer_id, relayed.clone())
+    }
+
+    fn negotiation(protocol: &[u8]) -> Vec<u8> {
+        let mut wire = b"\x13/multistream/1.0.0\n".to_vec();
+        wire.push(protocol.len() as u8 + 1);
+        wire.extend_from_slice(protocol);
+        wire.push(b'\n');
+        wire
+    }
+
+    #[test]
+    fn counts_circuits_opened_through_us() {
+        let peer_id = Keypair::generate_ed25519().public().to_peer_id();
+        let relayed = Relayed::default();
+        let mut wire = negotiation(HOP_PROTOCOL);
+        let proposal = wire.len();
+        wire.extend_from_slice(&[0; 100]);
+
+        let mut stream = substream(peer_id, &relayed, wire, true);
+        block_on(stream.read_exact(&mut vec![0; proposal])).unwrap();
+        block_on(stream.read_to_end(&mut vec![])).unwrap();
+
+        assert_eq!(relayed.0.lock().unwrap()[&peer_id], 100);
+    }
+
+    #[test]
+    fn ignores_other_protocols() {
+        let peer_id = Keypair::generate_ed25519().public().to_peer_id();
+        let relayed = Relayed::default();
+        let mut wire = negotiation(b"/ipfs/ping/1.0.0");
+        wire.extend_from_slice(&[0; 100]);
+
+        let mut stream = substream(peer_id, &relayed, wire, true);
+        block_on(stream.read_to_end(&mut vec![])).unwrap();
+        // A stop-protocol proposed by the remote is not one of our circuits.
+        let mut stream = substream(peer_id, &relayed, negotiation(STOP_PROTOCOL), true);
+        block_on(stream.read_to_end(&mut vec![])).unwrap();
+
+        assert!(relayed.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use libp2p::{Swarm, multiaddr::Protocol};
+use serde::Serialize;
+use serde_yaml::Value;
+use tracing::warn;
+
+use crate::VpnBehaviour;
+use crate::config::{Config, source};
+use crate::swarm::{self, Node};
+
+/// Keys applied to the running node, a section covers all of its keys.
+const RUNTIME: &[&str] = &[
+    "bootstrap",
+    "peers",
+    "acl",
+    "discovery.auto_relay",
+    "rendezvous",
+    "relay_client",
+    "connection_limits",
+    "ping.max_failures",
+    "gossip",
+    "kademlia",
+    "shutdown_timeout",
+];
+
+/// The outcome of reloading the configuration.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Keys changed and applied to the running node.
+    pub applied: Vec<String>,
+    /// Keys differing from the configuration the node started with, which take effect on the
+    /// next start.
+    pub restart_required: Vec<String>,
+}
+
+/// The keys whose values differ, compared down to the keys of the sections, e.g.
+/// `discovery.mdns`.
+pub fn diff(old: &Config, new: &Config) -> Vec<String> {
+    let old = serde_yaml::to_value(old).unwrap_or(Value::Null);
+    let new = serde_yaml::to_value(new).unwrap_or(Value::Null);
+
+    let mut keys = vec![];
+    for (key, old, new) in pairs(&old, &new) {
+        match (old, new) {
+            (Value::Mapping(_), Value::Mapping(_)) => {
+                for (field, old, new) in pairs(old, new) {
+                    if old != new {
+                        keys.push(format!("{key}.{field}"));
+                    }
+                }
+            }
+            _ if old != new => keys.push(key),
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// The keys of `new` with their values in both mappings.
+fn pairs<'a>(old: &'a Value, new: &'a Value) -> Vec<(String, &'a Value, &'a Value)> {
+    let Value::Mapping(mapping) = new else {
+        return vec![];
+    };
+
+    mapping
+        .iter()
+        .filter_map(|(key, new)| {
+            let value = old.get(key).unwrap_or(&Value::Null);
+            key.as_str().map(|key| (key.to_string(), value, new))
+        })
+        .collect()
+}
+
+/// Whether `key` can change while running. The rendezvous-client can be paused and resumed,
+/// but only if it was started.
+fn at_runtime(key: &str, swarm: &Swarm<VpnBehaviour>) -> bool {
+    match key {
+        "discovery.rendezvous" => swarm.behaviour().rendezvous.is_enabled(),
+        _ => RUNTIME.iter().any(|runtime| covers(runtime, key)),
+    }
+}
+
+/// Whether `key` is `section` or one of its keys.
+fn covers(section: &str, key: &str) -> bool {
+    key.strip_prefix(section)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Load the configuration again and apply what changed to the running node, the tunnels and
+/// connections are kept. On error the running configuration stays in place.
+pub(crate) fn reload(
+    swarm: &mut Swarm<VpnBehaviour>,
+    node: &mut Node,
+) -> Result<Report, source::Error> {
+    let config = Config::load(node.args.clone())?;
+
+    let report = Report {
+        applied: diff(&node.config, &config)
+            .into_iter()
+            .filter(|key| at_runtime(key, swarm))
+            .collect(),
+        restart_required: diff(&node.started, &config)
+            .into_iter()
+            .filter(|key| !at_runtime(key, swarm))
+            .collect(),
+    };
+    let changed = |section: &str| report.applied.iter().any(|key| covers(section, key));
+
+    if changed("bootstrap") {
+        for address in &config.bootstrap {
+            if !node.config.bootstrap.contains(address)
+                && let Err(e) = swarm.dial(address.clone())
+            {
+                warn!("Error dialing {}, {e}", address);
+            }
+        }
+    }
+
+    if changed("peers") {
+        swarm
+            .behaviour_mut()
+            .vpn
+            .set_peers(swarm::static_peers(&config));
+    }
+
+    if changed("acl") {
+        node.acl.set_config(config.acl.clone(), swarm);
+    }
+
+    if changed("discovery.rendezvous") || changed("rendezvous") {
+        let enabled = config.discovery.rendezvous && swarm.behaviour().rendezvous.is_enabled();
+        node.rendezvous
+            .set_config(enabled, config.rendezvous.clone());
+
+        // The others are dialed by the next maintenance.
+        for address in &config.rendezvous.points {
+            if let Some(Protocol::P2p(peer_id)) = address.iter().last()
+                && swarm.is_connected(&peer_id)
+            {
+                node.rendezvous.on_connected(peer_id);
+            }
+        }
+    }
+
+    if changed("discovery.auto_relay") || changed("relay_client") {
+        node.autorelay
+            .set_config(config.discovery.auto_relay, config.relay_client.clone());
+    }
+
+    if changed("connection_limits") {
+        *swarm.behaviour_mut().connection_limits.limits_mut() =
+            config.connection_limits.clone().into();
+    }
+
+    if changed("ping.max_failures") {
+        node.latencies.set_max_failures(config.ping.max_failures);
+    }
+
+    if changed("gossip") {
+        node.gossip.set_config(config.gossip.clone());
+    }
+
+    if changed("kademlia") {
+        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+            kademlia.set_mode(config.kademlia.mode.into());
+        }
+        node.dht.set_config(config.kademlia.clone());
+    }
+
+    node.config = config;
+    Ok(report)
+}
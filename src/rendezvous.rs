@@ -0,0 +1,245 @@
+use libp2p::{Multiaddr, PeerId, multiaddr::Protocol, rendezvous};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::config::rendezvous::Rendezvous as Config;
+
+const REDIAL_DELAY_MIN: Duration = Duration::from_secs(1);
+const REDIAL_DELAY_MAX: Duration = Duration::from_secs(5 * 60);
+
+/// Schedule of a connected rendezvous-point.
+struct Point {
+    register_at: Instant,
+    discover_at: Instant,
+}
+
+/// Registers this node under the network's namespace at the rendezvous-points and discovers
+/// the other members through them.
+pub struct Rendezvous {
+    config: Config,
+    enabled: bool,
+    namespace: rendezvous::Namespace,
+    /// The configured rendezvous-points and their addresses.
+    points: HashMap<PeerId, Multiaddr>,
+    /// The connected rendezvous-points.
+    connected: HashMap<PeerId, Point>,
+    /// Next redial of each disconnected rendezvous-point and the delay after it, doubled on
+    /// every attempt and reset once connected.
+    redials: HashMap<PeerId, (Instant, Duration)>,
+    /// Where the last discovery at each rendezvous-point left off.
+    cookies: HashMap<PeerId, rendezvous::Cookie>,
+}
+
+impl Rendezvous {
+    pub fn new(
+        network_id: &str,
+        enabled: bool,
+        config: Config,
+    ) -> Result<Self, rendezvous::NamespaceTooLong> {
+        let namespace = rendezvous::Namespace::new(format!("vpnr/{network_id}"))?;
+
+        let points = points(&config);
+        // Dialed on start, redialed unless connected by then.
+        let redials = points
+            .keys()
+            .map(|peer_id| (*peer_id, redial(REDIAL_DELAY_MIN)))
+            .collect();
+
+        Ok(Self {
+            points,
+            config,
+            enabled,
+            namespace,
+            connected: HashMap::new(),
+            redials,
+            cookies: HashMap::new(),
+        })
+    }
+
+    /// Apply a reloaded configuration, pausing the registrations and discoveries unless
+    /// `enabled`. Points no longer configured are forgotten, new ones are dialed, connected
+    /// new ones have to be passed to [`Rendezvous::on_connected`].
+    pub fn set_config(&mut self, enabled: bool, config: Config) {
+        self.points = points(&config);
+        self.connected
+            .retain(|peer_id, _| self.points.contains_key(peer_id));
+        self.cookies
+            .retain(|peer_id, _| self.points.contains_key(peer_id));
+        self.redials
+            .retain(|peer_id, _| self.points.contains_key(peer_id));
+        for peer_id in self.points.keys() {
+            if !self.connected.contains_key(peer_id) {
+                self.redials
+                    .entry(*peer_id)
+                    .or_insert((Instant::now(), REDIAL_DELAY_MIN));
+            }
+        }
+        self.config = config;
+        self.enabled = enabled;
+    }
+
+    /// A connection to `peer_id` was established, register and discover if it is a
+    /// rendezvous-point.
+    pub fn on_connected(&mut self, peer_id: PeerId) {
+        if self.points.contains_key(&peer_id) && !self.connected.contains_key(&peer_id) {
+            debug!("Connected to rendezvous-point {}", peer_id);
+            self.redials.remove(&peer_id);
+            let now = Instant::now();
+            self.connected.insert(
+                peer_id,
+                Point {
+                    register_at: now,
+                    discover_at: now,
+                },
+            );
+        }
+    }
+
+    /// The last connection to `peer_id` closed, redial it if it is a rendezvous-point.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        if self.connected.remove(peer_id).is_some() {
+            debug!(
+                "Redialing rendezvous-point {} in {:?}",
+                peer_id, REDIAL_DELAY_MIN
+            );
+            self.redials.insert(*peer_id, redial(REDIAL_DELAY_MIN));
+        }
+    }
+
+    /// Register again at all rendezvous-points, after the external addresses changed.
+    pub fn schedule_register(&mut self) {
+        let now = Instant::now();
+        for point in self.connected.values_mut() {
+            point.register_at = now;
+        }
+    }
+
+    /// Run the registrations and discoveries which are due, returning the rendezvous-points
+    /// to redial.
+    pub fn maintain(
+        &mut self,
+        client: &mut rendezvous::client::Behaviour,
+    ) -> Vec<(PeerId, Multiaddr)> {
+        if !self.enabled {
+            return vec![];
+        }
+
+        let now = Instant::now();
+
+        let mut dials = vec![];
+        for (peer_id, (at, delay)) in self.redials.iter_mut() {
+            if *at <= now
+                && let Some(address) = self.points.get(peer_id)
+            {
+                *at = now + *delay;
+                *delay = (*delay * 2).min(REDIAL_DELAY_MAX);
+                dials.push((*peer_id, address.clone()));
+            }
+        }
+
+        for (peer_id, point) in self.connected.iter_mut() {
+            if point.register_at <= now {
+                point.register_at = now + self.config.ttl / 2;
+                // Fails without a confirmed external address, retried with the next one.
+                if let Err(e) = client.register(
+                    self.namespace.clone(),
+                    *peer_id,
+                    Some(self.config.ttl.as_secs()),
+                ) {
+                    debug!("Error registering at {}, {e}", peer_id);
+                }
+            }
+
+            if point.discover_at <= now {
+                point.discover_at = now + self.config.discover_interval;
+                client.discover(
+                    Some(self.namespace.clone()),
+                    self.cookies.get(peer_id).cloned(),
+                    None,
+                    *peer_id,
+                );
+            }
+        }
+
+        dials
+    }
+
+    /// Handle an event of the client, returning the members discovered.
+    pub fn on_event(&mut self, event: rendezvous::client::Event) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        match event {
+            rendezvous::client::Event::Discovered {
+                rendezvous_node,
+                registrations,
+                cookie,
+            } => {
+                self.cookies.insert(rendezvous_node, cookie);
+                registrations
+                    .into_iter()
+                    .map(|registration| {
+                        (
+                            registration.record.peer_id(),
+                            registration.record.addresses().to_vec(),
+                        )
+                    })
+                    .collect()
+            }
+            rendezvous::client::Event::Registered {
+                rendezvous_node,
+                ttl,
+                namespace,
+            } => {
+                info!(
+                    "Registered as {} at {} for {}s",
+                    namespace, rendezvous_node, ttl
+                );
+                vec![]
+            }
+            rendezvous::client::Event::RegisterFailed {
+                rendezvous_node,
+                namespace,
+                error,
+            } => {
+                warn!(
+                    "Registering as {} at {} failed, {:?}",
+                    namespace, rendezvous_node, error
+                );
+                vec![]
+            }
+            rendezvous::client::Event::DiscoverFailed {
+                rendezvous_node,
+                error,
+                ..
+            } => {
+                warn!("Discovering at {} failed, {:?}", rendezvous_node, error);
+                // The cookie may be the cause, start over.
+                self.cookies.remove(&rendezvous_node);
+                vec![]
+            }
+            rendezvous::client::Event::Expired { peer } => {
+                debug!("Registration of {} expired", peer);
+                vec![]
+            }
+        }
+    }
+}
+
+/// The next redial, after `delay`, and the delay after it.
+fn redial(delay: Duration) -> (Instant, Duration) {
+    (Instant::now() + delay, (delay * 2).min(REDIAL_DELAY_MAX))
+}
+
+/// The configured rendezvous-points by their peer-id.
+fn points(config: &Config) -> HashMap<PeerId, Multiaddr> {
+    config
+        .points
+        .iter()
+        .filter_map(|address| match address.iter().last() {
+            Some(Protocol::P2p(peer_id)) => Some((peer_id, address.clone())),
+            _ => {
+                warn!("Ignoring rendezvous-point {}, missing /p2p/", address);
+                None
+            }
+        })
+        .collect()
+}
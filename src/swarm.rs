@@ -1,139 +1,282 @@
-use futures::StreamExt;
+use futures::{AsyncRead, AsyncWrite, FutureExt, StreamExt, future::Either};
 use libp2p::{
-    Multiaddr, Swarm, Transport, allow_block_list,
+    Multiaddr, PeerId, StreamProtocol, Swarm, Transport, allow_block_list,
     autonat::{self},
     connection_limits,
-    core::upgrade::Version,
-    dcutr, identify,
+    core::{transport::ListenerId, upgrade::Version},
+    dcutr, gossipsub, identify,
     identity::Keypair,
     kad, mdns, memory_connection_limits,
     multiaddr::Protocol,
-    noise,
-    pnet::{PnetConfig, PreSharedKey},
-    relay,
-    swarm::{SwarmEvent, behaviour::toggle::Toggle},
+    noise, ping, quic, relay, rendezvous,
+    swarm::{
+        SwarmEvent,
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+    },
     tcp, upnp, yamux,
 };
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
+use std::process::ExitCode;
 use std::time::Duration;
-use tracing::{info, trace};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{debug, error, info, trace, warn};
 
-use crate::{VpnBehaviour, VpnBehaviourEvent, config::Config, vpn};
+use crate::{
+    VpnBehaviour, VpnBehaviourEvent,
+    acl::Acl,
+    autorelay::AutoRelay,
+    cli::CliArgs,
+    config::Config,
+    control::{self, Control},
+    dht::{self, Dht},
+    gossip::{self, Gossip},
+    interface::Interface,
+    latency::Latencies,
+    metrics::{self, Metrics, TunOperation},
+    network::NetworkKey,
+    relay_server::{Counting, MembersOnly, RelayServer, Relayed},
+    reload,
+    rendezvous::Rendezvous,
+    vpn,
+};
+
+/// Time to let the departure-announcement and the port-mapping removals go out, on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
 
 pub(crate) fn build(
     keypair: &Keypair,
-    psk: PreSharedKey,
+    network: Option<NetworkKey>,
     config: Config,
+    metrics: Metrics,
+    relayed: Relayed,
 ) -> Result<Swarm<VpnBehaviour>, Box<dyn Error>> {
+    let static_secret = vpn::wireguard::static_secret(keypair)
+        .ok_or("Deriving the WireGuard key requires an ed25519 identity")?;
+    let ip_addr = config
+        .overlay_address(&keypair.public().to_peer_id())
+        .ok_or("The network-prefix leaves no room for host-addresses")?;
+
+    // Nodes of different networks must not merge their DHTs, nor with the public one.
+    let network_id = config.network_id(network.as_ref());
+    let kad_protocol = StreamProtocol::try_from_owned(format!("/vpnr/{network_id}/kad/1.0.0"))?;
+    info!("Using DHT-protocol {}", kad_protocol);
+
+    let private = network.as_ref().map(|network| {
+        vpn::transport::Transport::new(network.transport_key())
+            .with_rekey_limits(config.rekey.clone().into())
+    });
+    let membership = network.as_ref().map(|network| {
+        vpn::transport::Membership::new(network.clone(), keypair.public().to_peer_id())
+    });
+    let (relay_transport, relay_client) = relay::client::new(keypair.public().to_peer_id());
+
     let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone())
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default().nodelay(true),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_quic()
         .with_other_transport(|key| {
-            let noise_config = noise::Config::new(key).unwrap();
-            let yamux_config = yamux::Config::default();
-            let psk_encrypted = tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
-                .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket));
-            psk_encrypted
+            let private = private.clone();
+            let relayed = relayed.clone();
+            tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
+                .and_then(move |socket, _| wrap(private, socket))
                 .upgrade(Version::V1)
-                .authenticate(noise_config)
-                .multiplex(yamux_config)
+                .authenticate(noise::Config::new(key).unwrap())
+                .multiplex(yamux::Config::default())
+                .map(move |(peer_id, muxer), _| (peer_id, Counting::new(peer_id, muxer, relayed)))
+        })?
+        // QUIC brings its own security-upgrade and can not be wrapped, in a private network
+        // both sides prove their membership before the connection is used.
+        .with_other_transport(|key| {
+            let relayed = relayed.clone();
+            quic::tokio::Transport::new(quic::Config::new(key))
+                .and_then(move |output, endpoint| async move {
+                    match membership {
+                        Some(membership) => membership.verify(output, endpoint).await,
+                        None => Ok(output),
+                    }
+                })
+                .map(move |(peer_id, muxer), _| (peer_id, Counting::new(peer_id, muxer, relayed)))
+        })?
+        // Circuits are wrapped like TCP, the relay only forwards the encrypted frames.
+        .with_other_transport(|key| {
+            let private = private.clone();
+            relay_transport
+                .and_then(move |circuit, _| wrap(private, circuit))
+                .upgrade(Version::V1Lazy)
+                .authenticate(noise::Config::new(key).unwrap())
+                .multiplex(yamux::Config::default())
+                .map(move |(peer_id, muxer), _| (peer_id, Counting::new(peer_id, muxer, relayed)))
         })?
         .with_dns()?
-        .with_behaviour(|keypair| VpnBehaviour {
-            blocked_peers: allow_block_list::Behaviour::default(),
-            connection_limits: connection_limits::Behaviour::new(config.connection_limits.into()),
-            memory_limits: memory_connection_limits::Behaviour::with_max_bytes(config.memory_limit),
-
-            // Toggle::from(Some(ping::Behaviour::default())),
-            ping: Toggle::from(None),
-
-            identify: Toggle::from(match config.discovery.identify {
-                true => Some(identify::Behaviour::new(identify::Config::new(
-                    identify::PROTOCOL_NAME.to_string(),
-                    keypair.public(),
-                ))),
-                false => {
-                    println!("Not using identify ...");
-                    None
-                }
-            }),
-
-            autonat: Toggle::from(match config.discovery.autonat {
-                true => Some(autonat::Behaviour::new(
-                    keypair.public().to_peer_id(),
-                    config.autonat.into(),
-                )),
-                false => {
-                    println!("Not using autonat ...");
-                    None
-                }
-            }),
-
-            dcutr: Toggle::from(match config.discovery.dcutr {
-                true => Some(dcutr::Behaviour::new(keypair.public().to_peer_id())),
-                false => {
-                    println!("Not using dcutr ...");
-                    None
-                }
-            }),
-
-            mdns: Toggle::from(match config.discovery.mdns {
-                true => match mdns::tokio::Behaviour::new(
-                    mdns::Config {
-                        ttl: Duration::from_secs(6 * 60),
-                        query_interval: Duration::from_secs(5 * 60),
-                        enable_ipv6: false,
+        .with_behaviour(|keypair| {
+            let vpn = vpn::behaviour::Behaviour::new(vpn::config::Config {
+                addresses: vec![ip_addr],
+                address_prefix: match config.ip_addr {
+                    Some(_) => None,
+                    _ => Some(config.network_prefix),
+                },
+                network_prefix: Some(config.network_prefix),
+                mtu: config.mtu,
+                routes: config.routes.clone(),
+                peers: static_peers(&config),
+                network: network.clone(),
+                metrics: metrics.clone(),
+                ..vpn::config::Config::new(keypair.public().to_peer_id(), static_secret)
+            });
+            let members = vpn.members();
+
+            VpnBehaviour {
+                blocked_peers: allow_block_list::Behaviour::default(),
+                connection_limits: connection_limits::Behaviour::new(
+                    config.connection_limits.into(),
+                ),
+                memory_limits: memory_connection_limits::Behaviour::with_max_bytes(
+                    config.memory_limit,
+                ),
+
+                ping: Toggle::from(match config.discovery.ping {
+                    true => Some(ping::Behaviour::new(config.ping.clone().into())),
+                    false => {
+                        println!("Not using ping ...");
+                        None
+                    }
+                }),
+
+                identify: Toggle::from(match config.discovery.identify {
+                    true => Some(identify::Behaviour::new(identify::Config::new(
+                        identify::PROTOCOL_NAME.to_string(),
+                        keypair.public(),
+                    ))),
+                    false => {
+                        println!("Not using identify ...");
+                        None
+                    }
+                }),
+
+                autonat: Toggle::from(match config.discovery.autonat {
+                    true => Some(autonat::Behaviour::new(
+                        keypair.public().to_peer_id(),
+                        config.autonat.into(),
+                    )),
+                    false => {
+                        println!("Not using autonat ...");
+                        None
+                    }
+                }),
+
+                dcutr: Toggle::from(match config.discovery.dcutr {
+                    true => Some(dcutr::Behaviour::new(keypair.public().to_peer_id())),
+                    false => {
+                        println!("Not using dcutr ...");
+                        None
+                    }
+                }),
+
+                mdns: Toggle::from(match config.discovery.mdns {
+                    true => mdns(keypair.public().to_peer_id()),
+                    false => {
+                        println!("Not using mDNS ...");
+                        None
+                    }
+                }),
+
+                upnp: Toggle::from(match config.discovery.upnp {
+                    true => Some(upnp::tokio::Behaviour::default()),
+                    false => {
+                        println!("Not using UPnP ...");
+                        None
+                    }
+                }),
+
+                kademlia: Toggle::from(match config.discovery.dht {
+                    true => {
+                        let mut kad_config = kad::Config::new(kad_protocol);
+                        // Records are stored once their publisher is a member and they verify.
+                        kad_config.set_record_filtering(kad::StoreInserts::FilterBoth);
+
+                        let mut kademlia = kad::Behaviour::with_config(
+                            keypair.public().to_peer_id(),
+                            kad::store::MemoryStore::new(keypair.public().to_peer_id()),
+                            kad_config,
+                        );
+                        kademlia.set_mode(config.kademlia.mode.into());
+                        Some(kademlia)
+                    }
+                    false => {
+                        println!("Not using DHT ...");
+                        None
+                    }
+                }),
+
+                gossipsub: Toggle::from(match config.discovery.gossipsub {
+                    true => match gossipsub::ConfigBuilder::default()
+                        .validation_mode(gossipsub::ValidationMode::Strict)
+                        // Announcements are forwarded once `gossip::Gossip::on_message` accepted them.
+                        .validate_messages()
+                        .build()
+                        .map_err(|e| e.to_string())
+                        .and_then(|gossipsub_config| {
+                            gossipsub::Behaviour::new(
+                                gossipsub::MessageAuthenticity::Signed(keypair.clone()),
+                                gossipsub_config,
+                            )
+                            .map_err(String::from)
+                        }) {
+                        Ok(gossipsub) => Some(gossipsub),
+                        Err(e) => {
+                            println!("Error initializing gossipsub, {e}");
+                            None
+                        }
                     },
-                    keypair.public().to_peer_id(),
-                ) {
-                    Ok(mdns) => Some(mdns),
-                    Err(e) => {
-                        println!("Error initializing mDNS, {e}");
+                    false => {
+                        println!("Not using gossipsub ...");
                         None
                     }
-                },
-                false => {
-                    println!("Not using mDNS ...");
-                    None
-                }
-            }),
-
-            upnp: Toggle::from(match config.discovery.upnp {
-                true => Some(upnp::tokio::Behaviour::default()),
-                false => {
-                    println!("Not using UPnP ...");
-                    None
-                }
-            }),
-
-            kademlia: Toggle::from(match config.discovery.dht {
-                true => Some(kad::Behaviour::with_config(
-                    keypair.public().to_peer_id(),
-                    kad::store::MemoryStore::new(keypair.public().to_peer_id()),
-                    kad::Config::new(kad::PROTOCOL_NAME),
-                )),
-                false => {
-                    println!("Not using DHT ...");
-                    None
-                }
-            }),
-
-            relay: Toggle::from(match config.enable_relay {
-                true => Some(relay::Behaviour::new(
-                    keypair.public().to_peer_id(),
-                    relay::Config::default(),
-                )),
-                false => {
-                    println!("Not using relay ...");
-                    None
-                }
-            }),
-            vpn: vpn::behaviour::Behaviour::new(vpn::config::Config::default()),
+                }),
+
+                rendezvous: Toggle::from(match config.discovery.rendezvous {
+                    true => Some(rendezvous::client::Behaviour::new(keypair.clone())),
+                    false => {
+                        println!("Not using rendezvous ...");
+                        None
+                    }
+                }),
+
+                rendezvous_server: Toggle::from(match config.discovery.rendezvous_server {
+                    true => Some(rendezvous::server::Behaviour::new(
+                        rendezvous::server::Config::default(),
+                    )),
+                    false => {
+                        println!("Not acting as rendezvous-point ...");
+                        None
+                    }
+                }),
+
+                relay_client,
+
+                relay: Toggle::from(match config.enable_relay {
+                    true => {
+                        let mut relay_config: relay::Config = config.relay.clone().into();
+                        if config.relay.members_only && network.is_some() {
+                            relay_config
+                                .reservation_rate_limiters
+                                .push(Box::new(MembersOnly(members.clone())));
+                            relay_config
+                                .circuit_src_rate_limiters
+                                .push(Box::new(MembersOnly(members)));
+                        }
+                        Some(relay::Behaviour::new(
+                            keypair.public().to_peer_id(),
+                            relay_config,
+                        ))
+                    }
+                    false => {
+                        println!("Not using relay ...");
+                        None
+                    }
+                }),
+                vpn,
+            }
         })?
         .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(u64::MAX)))
         .build();
@@ -149,21 +292,408 @@ pub(crate) fn build(
     info!("Listening on interface {}", listen_udp);
     swarm.listen_on(listen_udp)?;
 
+    if let Some(gossipsub) = swarm.behaviour_mut().gossipsub.as_mut() {
+        gossipsub.subscribe(&gossip::topic(&network_id))?;
+    }
+
     for address in config.bootstrap {
         swarm.dial(address)?;
     }
 
+    if config.discovery.rendezvous {
+        for address in config.rendezvous.points {
+            swarm.dial(address)?;
+        }
+    }
+
     Ok(swarm)
 }
 
-pub async fn run(mut swarm: Swarm<VpnBehaviour>) -> Result<(), Box<dyn Error>> {
-    // Kick it off
+/// Wrap `socket` with the private network's encryption, if any.
+async fn wrap<S>(
+    private: Option<vpn::transport::Transport>,
+    socket: S,
+) -> Result<Either<vpn::transport::Output<S>, S>, vpn::transport::Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    match private {
+        Some(private) => private.handshake(socket).await.map(Either::Left),
+        None => Ok(Either::Right(socket)),
+    }
+}
+
+/// The statically configured peers, as the VPN-behaviour takes them.
+pub(crate) fn static_peers(config: &Config) -> HashMap<PeerId, vpn::config::Peer> {
+    config
+        .peers
+        .iter()
+        .map(|peer| {
+            (
+                peer.peer_id,
+                vpn::config::Peer {
+                    addresses: peer.addresses.clone(),
+                    allowed_ips: peer.allowed_ips.clone(),
+                    persistent_keepalive: peer.persistent_keepalive,
+                },
+            )
+        })
+        .collect()
+}
+
+/// The mDNS-behaviour, `None` if it can not be initialized.
+fn mdns(local_peer_id: PeerId) -> Option<mdns::tokio::Behaviour> {
+    match mdns::tokio::Behaviour::new(
+        mdns::Config {
+            ttl: Duration::from_secs(6 * 60),
+            query_interval: Duration::from_secs(5 * 60),
+            enable_ipv6: false,
+        },
+        local_peer_id,
+    ) {
+        Ok(mdns) => Some(mdns),
+        Err(e) => {
+            println!("Error initializing mDNS, {e}");
+            None
+        }
+    }
+}
+
+/// The features of this node, announced to the network.
+pub(crate) fn capabilities(config: &Config) -> BTreeSet<String> {
+    [
+        ("dht", config.discovery.dht),
+        ("relay", config.enable_relay),
+        ("dcutr", config.discovery.dcutr),
+        ("autonat", config.discovery.autonat),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(capability, _)| capability.to_string())
+    .collect()
+}
+
+/// Run the due DHT-, gossip- and rendezvous-maintenance, of whichever is enabled.
+fn maintain(
+    swarm: &mut Swarm<VpnBehaviour>,
+    dht: &mut Dht,
+    gossip: &mut Gossip,
+    rendezvous: &mut Rendezvous,
+) {
+    let overlay = swarm.behaviour().vpn.addresses().to_vec();
+    let routes = swarm.behaviour().vpn.advertised_routes().to_vec();
+    let addresses: Vec<Multiaddr> = swarm
+        .external_addresses()
+        .chain(swarm.listeners())
+        .cloned()
+        .collect();
+
+    if let Some(gossipsub) = swarm.behaviour_mut().gossipsub.as_mut() {
+        gossip.maintain(gossipsub, &overlay, &routes, addresses.clone());
+    }
+
+    if let Some(client) = swarm.behaviour_mut().rendezvous.as_mut() {
+        for (peer_id, address) in rendezvous.maintain(client) {
+            let opts = DialOpts::peer_id(peer_id)
+                .addresses(vec![address])
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            if let Err(e) = swarm.dial(opts) {
+                debug!("Error redialing rendezvous-point {}, {e}", peer_id);
+            }
+        }
+    }
+
+    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+        let events = dht.maintain(kademlia, &overlay, addresses);
+        on_dht_events(swarm, events);
+    }
+}
+
+fn on_dht_events(swarm: &mut Swarm<VpnBehaviour>, events: Vec<dht::Event>) {
+    for event in events {
+        match event {
+            dht::Event::Resolved {
+                destination,
+                record,
+            } => swarm
+                .behaviour_mut()
+                .vpn
+                .resolved(destination, record.peer_id, record.addresses),
+            dht::Event::Unresolved { destination } => {
+                swarm.behaviour_mut().vpn.unresolved(destination)
+            }
+            dht::Event::QueryFailed { query, error } => {
+                warn!(%query, %error, "DHT query failed")
+            }
+        }
+    }
+}
+
+/// The state the event-loop keeps beside the swarm.
+pub(crate) struct Node {
+    pub interface: Interface,
+    pub dht: Dht,
+    pub gossip: Gossip,
+    pub rendezvous: Rendezvous,
+    pub autorelay: AutoRelay,
+    pub relay_server: RelayServer,
+    pub latencies: Latencies,
+    pub control: Control,
+    pub acl: Acl,
+    pub metrics: Metrics,
+    /// The libp2p-metrics, `None` unless served.
+    pub p2p_metrics: Option<libp2p::metrics::Metrics>,
+    /// The key of the private network, `None` in a public one.
+    pub network: Option<NetworkKey>,
+    /// The running configuration, updated on reload.
+    pub config: Config,
+    /// The configuration the node started with, what differs from it requires a restart.
+    pub started: Config,
+    /// The arguments the configuration is reloaded with.
+    pub args: CliArgs,
+}
+
+/// How the node left the network.
+pub(crate) enum Exit {
+    /// Every step of the shutdown completed.
+    Clean,
+    /// The shutdown was cut short by its deadline.
+    DeadlineExceeded,
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        match exit {
+            Exit::Clean => ExitCode::SUCCESS,
+            Exit::DeadlineExceeded => ExitCode::from(2),
+        }
+    }
+}
+
+/// Leave the network: announce the departure, close the listeners so UPnP removes its
+/// port-mappings, then close the connections and with them the VPN-streams.
+async fn shutdown(
+    swarm: &mut Swarm<VpnBehaviour>,
+    gossip: &mut Gossip,
+    listeners: HashSet<ListenerId>,
+) {
+    if let Some(gossipsub) = swarm.behaviour_mut().gossipsub.as_mut() {
+        gossip.leave(gossipsub);
+    }
+    for listener_id in listeners {
+        swarm.remove_listener(listener_id);
+    }
+
+    // The announcement and the removal of the port-mappings go out while the swarm is polled.
+    let grace = tokio::time::sleep(SHUTDOWN_GRACE);
+    tokio::pin!(grace);
     loop {
         tokio::select! {
-            event = swarm.select_next_some() => match event {
+            _ = &mut grace => break,
+            event = swarm.select_next_some() => trace!("{:?}", event),
+        }
+    }
+
+    let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    info!("Disconnecting from {} peers", peers.len());
+    for peer_id in peers {
+        // Fails only if the peer disconnected meanwhile.
+        let _ = swarm.disconnect_peer_id(peer_id);
+    }
+    while swarm.connected_peers().next().is_some() {
+        let event = swarm.select_next_some().await;
+        trace!("{:?}", event);
+    }
+}
+
+pub(crate) async fn run(
+    mut swarm: Swarm<VpnBehaviour>,
+    mut node: Node,
+) -> Result<Exit, Box<dyn Error>> {
+    let mut maintenance = tokio::time::interval(Duration::from_secs(1));
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut listeners = HashSet::new();
+
+    // Kick it off
+    let received = loop {
+        tokio::select! {
+            _ = sigint.recv() => break Ok("SIGINT"),
+            _ = sigterm.recv() => break Ok("SIGTERM"),
+
+            _ = sighup.recv() => match reload::reload(&mut swarm, &mut node) {
+                Ok(report) => {
+                    info!("Reloaded configuration, applied {:?}", report.applied);
+                    if !report.restart_required.is_empty() {
+                        warn!("Changes to {:?} take effect after a restart", report.restart_required);
+                    }
+                }
+                Err(e) => warn!("Error reloading configuration, keeping the running one, {e}"),
+            },
+
+            (request, reply) = node.control.next() => {
+                control::handle(&mut swarm, &mut node, request, reply);
+            },
+
+            _ = maintenance.tick() => {
+                maintain(&mut swarm, &mut node.dht, &mut node.gossip, &mut node.rendezvous);
+                node.autorelay.maintain(&mut swarm);
+                node.control.expire();
+                swarm.behaviour_mut().vpn.choose_tunnels(|connection_id| {
+                    node.latencies.get(connection_id).map(|latency| latency.smoothed_rtt)
+                });
+            },
+
+            packet = node.interface.read() => match packet {
+                Ok(packet) => {
+                    trace!("Interface, read {} bytes", packet.len());
+                    swarm.behaviour_mut().vpn.send_packet(packet);
+                }
+                Err(e) => {
+                    node.metrics.tun_error(TunOperation::Read);
+                    // The device does not recover, e.g. once removed, and is ready again at once.
+                    break Err(e);
+                }
+            },
+
+            event = swarm.select_next_some().inspect(|event| {
+                if let Some(p2p_metrics) = &node.p2p_metrics {
+                    metrics::record(p2p_metrics, event);
+                }
+            }) => match event {
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Vpn(vpn::behaviour::Event::Packet { peer_id, packet })) => {
+                    trace!("vpn::Event::Packet, {} bytes from {}", packet.len(), peer_id);
+                    if node.control.on_packet(peer_id, &packet) {
+                        trace!("Overlay-ping answered by {}", peer_id);
+                    } else if let Err(e) = node.interface.write(&packet).await {
+                        warn!("Error writing to interface, {e}");
+                        node.metrics.tun_error(TunOperation::Write);
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Vpn(vpn::behaviour::Event::Established { peer_id, .. })) => {
+                    info!("VPN-stream established with {}", peer_id);
+                    for (net, peer_id) in swarm.behaviour().vpn.routing_table().routes() {
+                        debug!("Route {} via {}", net, peer_id);
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Vpn(vpn::behaviour::Event::AddressChanged { old, new })) => {
+                    info!("Overlay-address {} conflicted, changing to {}", old, new);
+                    if let Err(e) = node.interface.set_address(new) {
+                        warn!("Error changing interface address, {e}");
+                    }
+                    node.dht.schedule_publish();
+                    node.gossip.schedule_publish();
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Vpn(vpn::behaviour::Event::Unresolved { destination })) => {
+                    // The members' announcements answer without a round-trip through the DHT.
+                    let announced = node.gossip
+                        .resolve(destination)
+                        .map(|announcement| (announcement.peer_id, announcement.listen_addrs.clone()));
+
+                    match (announced, swarm.behaviour_mut().kademlia.as_mut()) {
+                        (Some((peer_id, listen_addrs)), _) => swarm.behaviour_mut().vpn.resolved(destination, peer_id, listen_addrs),
+                        (None, Some(kademlia)) => node.dht.lookup(kademlia, destination),
+                        (None, None) => swarm.behaviour_mut().vpn.unresolved(destination),
+                    }
+                }
+
+                SwarmEvent::NewListenAddr { listener_id, address } => {
+                    info!("Listening on {}", address);
+                    listeners.insert(listener_id);
+                    node.dht.schedule_publish();
+                    node.gossip.schedule_publish();
+                }
+
+                SwarmEvent::ExternalAddrConfirmed { address } => {
+                    info!("External address confirmed, {}", address);
+                    node.dht.schedule_publish();
+                    node.gossip.schedule_publish();
+                    node.rendezvous.schedule_register();
+                }
+
+                SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                    trace!("Connection established with {}", peer_id);
+                    node.latencies.on_connected(peer_id, connection_id, endpoint.is_relayed());
+                    node.rendezvous.on_connected(peer_id);
+                    node.autorelay.on_connected(peer_id, endpoint.is_dialer().then(|| endpoint.get_remote_address()));
+                    node.control.on_connected(peer_id, connection_id, endpoint);
+                }
+
+                SwarmEvent::ConnectionClosed { peer_id, connection_id, num_established, .. } => {
+                    node.latencies.on_closed(&connection_id);
+                    node.control.on_closed(&connection_id);
+                    if num_established == 0 {
+                        trace!("Disconnected from {}", peer_id);
+                        node.rendezvous.on_disconnected(&peer_id);
+                        node.autorelay.on_disconnected(&peer_id);
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Ping(event)) => {
+                    trace!("ping::Event, {:?}", event);
+                    if let Some(connection_id) = node.latencies.on_ping(event) {
+                        swarm.close_connection(connection_id);
+                    }
+                }
+
+                SwarmEvent::ListenerClosed { listener_id, addresses, reason } => {
+                    debug!("Listener on {:?} closed, {:?}", addresses, reason);
+                    listeners.remove(&listener_id);
+                    node.autorelay.on_listener_closed(listener_id);
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Autonat(autonat::Event::StatusChanged { old, new })) => {
+                    info!("NAT-status changed from {:?} to {:?}", old, new);
+                    node.autorelay.on_nat_status(&new);
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Relay(event)) => {
+                    node.relay_server.on_event(event);
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted { relay_peer_id, renewal, .. })) => {
+                    match renewal {
+                        true => debug!("Reservation on relay {} renewed", relay_peer_id),
+                        false => info!("Reservation on relay {} accepted", relay_peer_id),
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Rendezvous(event)) => {
+                    for (peer_id, addresses) in node.rendezvous.on_event(event) {
+                        if peer_id == *swarm.local_peer_id() || swarm.is_connected(&peer_id) {
+                            continue;
+                        }
+
+                        debug!("Discovered member {} through rendezvous", peer_id);
+                        if let Err(e) = swarm.dial(DialOpts::peer_id(peer_id).addresses(addresses).build()) {
+                            warn!("Error dialing {}, {e}", peer_id);
+                        }
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message_id, message })) => {
+                    let member = swarm.behaviour().vpn.is_member(&propagation_source);
+                    let acceptance = node.gossip.on_message(&message, member);
+                    if let Some(gossipsub) = swarm.behaviour_mut().gossipsub.as_mut() {
+                        gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance);
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, .. })) => {
+                    trace!("gossipsub::Event::Subscribed, {}", peer_id);
+                    // Let the new subscriber learn about this node without waiting an interval.
+                    node.gossip.schedule_publish();
+                }
 
                 SwarmEvent::Behaviour(VpnBehaviourEvent::Identify(identify::Event::Received { connection_id, peer_id, info })) => {
                     trace!("identify::Event::Received, received, {},{}, {:?}", connection_id, peer_id, info);
+                    node.autorelay.on_identified(peer_id, &info.listen_addrs, &info.protocols);
                     for address in info.listen_addrs {
                         trace!("{}", address);
                         swarm.add_peer_address(peer_id, address.clone());
@@ -186,60 +716,28 @@ pub async fn run(mut swarm: Swarm<VpnBehaviour>) -> Result<(), Box<dyn Error>> {
                 SwarmEvent::Behaviour(VpnBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, result, stats, step })) => {
                     trace!("kad::Event::OutboundQueryProgressed, {:?}, {:?}, {:?}, {:?}", id, result, stats, step);
 
-                    match result {
-                        kad::QueryResult::Bootstrap(result) => match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                                swarm.behaviour_mut().kademlia.as_mut().unwrap().get_closest_peers(result.peer);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::GetClosestPeers(result) => match result {
-                            Ok(result) => {
-                                for peer in result.peers {
-                                    for _address in peer.addrs {
-                                        // swarm.add_peer_address(peer.peer_id, address);
-                                    }
-                                }
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::GetProviders(result) => match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::StartProviding(result) =>  match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::RepublishProvider(result) => match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::GetRecord(result) => match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::PutRecord(result) => match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
-                        kad::QueryResult::RepublishRecord(result) => match result {
-                            Ok(result) => {
-                                trace!("{:?}", result);
-                            },
-                            Err(e) => trace!("{}", e),
-                        },
+                    let events = node.dht.on_query_progressed(id, result, step.last);
+                    on_dht_events(&mut swarm, events);
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Kademlia(kad::Event::InboundRequest { request: kad::InboundRequest::PutRecord { source, record: Some(record), .. } })) => {
+                    let member = swarm.behaviour().vpn.is_member(&source);
+                    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                        node.dht.on_put_record(kademlia, source, record, member);
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Kademlia(kad::Event::InboundRequest { request: kad::InboundRequest::AddProvider { record: Some(record) } })) => {
+                    let member = swarm.behaviour().vpn.is_member(&record.provider);
+                    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                        node.dht.on_add_provider(kademlia, record.provider, record, member);
+                    }
+                }
+
+                SwarmEvent::Behaviour(VpnBehaviourEvent::Kademlia(kad::Event::RoutingUpdated { peer, is_new_peer, .. })) => {
+                    trace!("kad::Event::RoutingUpdated, {}", peer);
+                    if is_new_peer {
+                        node.dht.on_routing_updated();
                     }
                 }
 
@@ -248,5 +746,36 @@ pub async fn run(mut swarm: Swarm<VpnBehaviour>) -> Result<(), Box<dyn Error>> {
                 }
             }
         }
+    };
+
+    match &received {
+        Ok(signal) => info!(
+            "Received {}, shutting down within {:?}",
+            signal, node.config.shutdown_timeout
+        ),
+        Err(e) => error!(
+            "Error reading from interface, {e}, shutting down within {:?}",
+            node.config.shutdown_timeout
+        ),
     }
+    let exit = match tokio::time::timeout(
+        node.config.shutdown_timeout,
+        shutdown(&mut swarm, &mut node.gossip, listeners),
+    )
+    .await
+    {
+        Ok(()) => Exit::Clean,
+        Err(_) => {
+            warn!(
+                "Shutdown exceeded {:?}, exiting",
+                node.config.shutdown_timeout
+            );
+            Exit::DeadlineExceeded
+        }
+    };
+
+    node.control.close();
+    node.interface.close();
+    received?;
+    Ok(exit)
 }
@@ -0,0 +1,17 @@
+//! Fixtures shared by the unit-tests.
+
+use libp2p::{PeerId, identity::Keypair};
+use std::fs;
+use std::path::PathBuf;
+
+/// The PeerId of a new ed25519-identity.
+pub fn peer_id() -> PeerId {
+    Keypair::generate_ed25519().public().to_peer_id()
+}
+
+/// A path in the temporary directory, removed before use.
+pub fn temporary(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("vpnr-{}-{name}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
@@ -0,0 +1,137 @@
+use ipnet::IpNet;
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Candidates tried for a free address, before keeping the conflicting one.
+const MAX_ATTEMPTS: u32 = 256;
+
+/// Derive the overlay-address of `peer_id` within `prefix`. Raising `salt` yields the next
+/// candidate, after a conflict. `None` if the prefix leaves no room for hosts.
+pub fn derive(peer_id: &PeerId, prefix: &IpNet, salt: u32) -> Option<IpAddr> {
+    let host_bits = u32::from(prefix.max_prefix_len() - prefix.prefix_len());
+    if host_bits < 2 {
+        return None;
+    }
+
+    let network = match prefix.network() {
+        IpAddr::V4(network) => u128::from(u32::from(network)),
+        IpAddr::V6(network) => u128::from(network),
+    };
+    let mask = u128::MAX >> (128 - host_bits);
+
+    // Skip the network- and broadcast-address.
+    let host = (0..)
+        .map(|round: u32| {
+            let hash = Sha256::new()
+                .chain_update(peer_id.to_bytes())
+                .chain_update(salt.to_be_bytes())
+                .chain_update(round.to_be_bytes())
+                .finalize();
+            u128::from_be_bytes(hash[..16].try_into().unwrap()) & mask
+        })
+        .find(|host| *host != 0 && *host != mask)?;
+
+    Some(match prefix {
+        IpNet::V4(_) => IpAddr::V4(Ipv4Addr::from((network | host) as u32)),
+        IpNet::V6(_) => IpAddr::V6(Ipv6Addr::from(network | host)),
+    })
+}
+
+/// Derive the next candidate after `salt` which is not `taken`, with the salt yielding it.
+/// `None` if the prefix leaves no room for hosts, or no candidate within `MAX_ATTEMPTS` is free.
+pub fn derive_free(
+    peer_id: &PeerId,
+    prefix: &IpNet,
+    mut salt: u32,
+    taken: &HashSet<IpAddr>,
+) -> Option<(IpAddr, u32)> {
+    for _ in 0..MAX_ATTEMPTS {
+        salt = salt.checked_add(1)?;
+        let address = derive(peer_id, prefix, salt)?;
+        if !taken.contains(&address) {
+            return Some((address, salt));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::peer_id;
+
+    #[test]
+    fn derived_address_is_a_host_of_the_prefix() {
+        for prefix in [
+            "10.0.0.0/8",
+            "192.168.7.0/30",
+            "fd00:abcd::/64",
+            "fd00::/126",
+        ] {
+            let prefix: IpNet = prefix.parse().unwrap();
+            for _ in 0..32 {
+                let address = derive(&peer_id(), &prefix, 0).unwrap();
+                assert!(prefix.contains(&address), "{address} in {prefix}");
+                assert_ne!(address, prefix.network(), "{prefix}");
+                assert_ne!(address, prefix.broadcast(), "{prefix}");
+            }
+        }
+    }
+
+    #[test]
+    fn derivation_is_stable() {
+        let peer_id = peer_id();
+        let prefix: IpNet = "10.0.0.0/8".parse().unwrap();
+
+        assert_eq!(derive(&peer_id, &prefix, 0), derive(&peer_id, &prefix, 0));
+    }
+
+    #[test]
+    fn salt_yields_another_candidate() {
+        let peer_id = peer_id();
+        let prefix: IpNet = "fd00::/64".parse().unwrap();
+
+        assert_ne!(derive(&peer_id, &prefix, 0), derive(&peer_id, &prefix, 1));
+    }
+
+    #[test]
+    fn prefix_without_hosts_is_rejected() {
+        for prefix in ["10.0.0.0/31", "10.0.0.1/32", "fd00::/127", "fd00::1/128"] {
+            let prefix: IpNet = prefix.parse().unwrap();
+            assert_eq!(derive(&peer_id(), &prefix, 0), None, "{prefix}");
+        }
+    }
+
+    #[test]
+    fn free_address_is_not_taken() {
+        let peer_id = peer_id();
+        let prefix: IpNet = "192.168.7.0/29".parse().unwrap();
+        let taken = HashSet::from([derive(&peer_id, &prefix, 1).unwrap()]);
+
+        let (address, salt) = derive_free(&peer_id, &prefix, 0, &taken).unwrap();
+        assert!(!taken.contains(&address));
+        assert_eq!(derive(&peer_id, &prefix, salt), Some(address));
+    }
+
+    #[test]
+    fn exhausted_prefix_yields_none() {
+        let prefix: IpNet = "192.168.7.0/30".parse().unwrap();
+        let taken: HashSet<IpAddr> = prefix.hosts().collect();
+
+        assert_eq!(derive_free(&peer_id(), &prefix, 0, &taken), None);
+    }
+
+    #[test]
+    fn exhausted_salt_yields_none() {
+        let (peer_id, prefix) = (peer_id(), "10.0.0.0/8".parse().unwrap());
+        let free = derive_free(&peer_id, &prefix, u32::MAX - 1, &HashSet::new());
+
+        assert_eq!(free.map(|(_, salt)| salt), Some(u32::MAX));
+        assert_eq!(
+            derive_free(&peer_id, &prefix, u32::MAX, &HashSet::new()),
+            None
+        );
+    }
+}
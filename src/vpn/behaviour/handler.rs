@@ -1,49 +1,187 @@
-use libp2p::swarm::handler::ConnectionEvent;
-use libp2p::swarm::handler::ProtocolSupport::{self};
-use libp2p::swarm::{ConnectionHandler, ConnectionHandlerEvent, SubstreamProtocol};
-use std::collections::HashSet;
-use std::convert::Infallible;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll, Waker};
-use tracing::trace;
+use boringtun::x25519::PublicKey;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use libp2p::swarm::handler::{
+    ConnectionEvent, DialUpgradeError, FullyNegotiatedInbound, FullyNegotiatedOutbound,
+    ListenUpgradeError,
+};
+use libp2p::swarm::{
+    ConnectionHandler, ConnectionHandlerEvent, StreamUpgradeError, SubstreamProtocol,
+};
+use libp2p::{PeerId, Stream};
+use std::collections::VecDeque;
+use std::io;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{trace, warn};
 
-use crate::vpn::behaviour::upgrade::VPN_PROTOCOL;
+use super::handshake::{Hello, Membership};
+use super::protocol::{self, MAX_PACKET_SIZE};
+use super::upgrade::{self, Upgrade};
+use crate::metrics::DropReason;
+use crate::vpn::config::Config;
+use crate::vpn::wireguard::{Output, Tunnel};
 
-use super::upgrade::Upgrade;
+/// Number of packets buffered while the tunnel or the outbound stream is not ready.
+const MAX_PENDING_PACKETS: usize = 256;
+
+/// Interval for driving the WireGuard timers, as recommended by boringtun.
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub enum Command {
+    /// Send an IP-packet to the remote.
+    SendPacket(Vec<u8>),
+    /// The local addresses changed, renegotiate the outbound stream to announce them.
+    Reconfigure(Config),
+    /// Admit the remote to the VPN, or expel it and close the streams.
+    Admit(bool),
+}
 
 #[derive(Debug)]
-pub enum Event {}
+pub enum Event {
+    /// The outbound stream is negotiated, packets can be sent on this connection.
+    Established(Hello),
+    /// The remote opened an inbound stream, announcing its current addresses and routes.
+    Updated(Hello),
+    /// The remote failed the handshake.
+    HandshakeFailed(upgrade::Error),
+    /// An IP-packet was received from the remote.
+    Packet(Vec<u8>),
+    /// IP-packets were passed to the tunnel since the last report.
+    Sent { packets: u64, bytes: u64 },
+    /// The outbound stream failed, packets are buffered until it is renegotiated.
+    Closed,
+}
+
+type SendFuture = BoxFuture<'static, Result<Stream, io::Error>>;
+type RecvFuture = BoxFuture<'static, Result<(Stream, Vec<u8>), io::Error>>;
+
+enum OutboundState {
+    /// The stream is ready to send the next packet.
+    Idle(Stream),
+    /// A packet is being written to the stream.
+    Sending(SendFuture),
+}
 
 pub struct Handler {
-    is_upgraded: bool,
-    shared_state: Arc<Mutex<SharedState>>,
+    config: Config,
+    remote_peer_id: PeerId,
+    /// Whether the remote may negotiate VPN-streams, otherwise the protocol is not offered.
+    admitted: bool,
+    /// The WireGuard session, once the remote's public key is known.
+    tunnel: Option<Tunnel>,
+    remote_public_key: Option<PublicKey>,
+    timer: Interval,
+    /// The stream we write datagrams to, once negotiated.
+    outbound: Option<OutboundState>,
+    /// Whether an outbound stream was requested and not yet failed.
+    outbound_requested: bool,
+    /// Whether the remote did not offer the protocol, until it opens a stream itself.
+    outbound_refused: bool,
+    /// The stream we read datagrams from, once negotiated by the remote.
+    inbound: Option<RecvFuture>,
+    /// IP-packets waiting for the tunnel.
+    pending_packets: VecDeque<Vec<u8>>,
+    /// Encrypted datagrams waiting for the outbound stream.
+    pending_datagrams: VecDeque<Vec<u8>>,
+    /// Packets and bytes passed to the tunnel, not yet reported.
+    sent: (u64, u64),
+    /// Events waiting to be reported to the behaviour.
+    pending_events: VecDeque<Event>,
 }
 
-impl Default for Handler {
-    fn default() -> Self {
+impl Handler {
+    pub fn new(config: Config, remote_peer_id: PeerId, admitted: bool) -> Self {
+        let mut timer = tokio::time::interval(TIMER_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
         Self {
-            is_upgraded: false,
-            shared_state: Arc::new(Mutex::new(SharedState {
-                completed: false,
-                waker: None,
-            })),
+            config,
+            remote_peer_id,
+            admitted,
+            tunnel: None,
+            remote_public_key: None,
+            timer,
+            outbound: None,
+            outbound_requested: false,
+            outbound_refused: false,
+            inbound: None,
+            pending_packets: VecDeque::new(),
+            pending_datagrams: VecDeque::new(),
+            sent: (0, 0),
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Start the WireGuard session once the remote's public key was received on either stream.
+    fn on_remote_hello(&mut self, hello: &Hello) {
+        let public_key = PublicKey::from(hello.public_key);
+        if self.remote_public_key == Some(public_key) {
+            return;
+        }
+
+        if self.remote_public_key.is_some() {
+            warn!("Remote changed its WireGuard public key, restarting session");
+        }
+
+        self.remote_public_key = Some(public_key);
+        self.tunnel = Some(Tunnel::new(
+            self.config.static_secret.clone(),
+            public_key,
+            self.config
+                .network
+                .as_ref()
+                .map(|network| network.wireguard_psk()),
+            self.config.persistent_keepalive,
+        ));
+    }
+
+    fn upgrade(&self) -> Upgrade {
+        let membership = self.config.network.clone().map(|network| Membership {
+            network,
+            local: self.config.local_peer_id,
+            remote: self.remote_peer_id,
+        });
+
+        Upgrade::new(self.config.hello(&self.remote_peer_id), membership)
+    }
+
+    fn on_tunnel_output(&mut self, output: Output) {
+        for datagram in output.network {
+            if self.pending_datagrams.len() >= MAX_PENDING_PACKETS {
+                warn!("Dropping datagram, outbound queue is full");
+                self.pending_datagrams.pop_front();
+                self.config.metrics.dropped(DropReason::HandlerQueueFull, 1);
+            }
+            self.pending_datagrams.push_back(datagram);
+        }
+
+        for packet in output.interface {
+            self.pending_events.push_back(Event::Packet(packet));
         }
     }
 }
 
-struct SharedState {
-    /// Whether or not the sleep time has elapsed
-    completed: bool,
+fn send(mut stream: Stream, packet: Vec<u8>) -> SendFuture {
+    async move {
+        protocol::send_packet(&mut stream, &packet).await?;
+        Ok(stream)
+    }
+    .boxed()
+}
 
-    /// The waker for the task that `TimerFuture` is running on.
-    /// The thread can use this after setting `completed = true` to tell
-    /// `TimerFuture`'s task to wake up, see that `completed = true`, and
-    /// move forward.
-    waker: Option<Waker>,
+fn recv(mut stream: Stream) -> RecvFuture {
+    async move {
+        let packet = protocol::recv_packet(&mut stream).await?;
+        Ok((stream, packet))
+    }
+    .boxed()
 }
 
 impl ConnectionHandler for Handler {
-    type FromBehaviour = Infallible;
+    type FromBehaviour = Command;
     type ToBehaviour = Event;
     type InboundProtocol = Upgrade;
     type OutboundProtocol = Upgrade;
@@ -53,47 +191,112 @@ impl ConnectionHandler for Handler {
 
     fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
         trace!("Handler::listen_protocol");
-        SubstreamProtocol::new(Upgrade::new(), ())
+        let mut upgrade = self.upgrade();
+        if !self.admitted {
+            upgrade.supported_protocols.clear();
+        }
+        SubstreamProtocol::new(upgrade, ())
     }
 
     fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
-        trace!("Handler::on_behaviour_event, {:?}", event);
+        match event {
+            Command::SendPacket(packet) => {
+                if packet.len() > MAX_PACKET_SIZE {
+                    warn!("Dropping packet of {} bytes, too large", packet.len());
+                    self.config.metrics.dropped(DropReason::TooLarge, 1);
+                    return;
+                }
 
-        match event {}
+                if self.pending_packets.len() >= MAX_PENDING_PACKETS {
+                    warn!("Dropping packet, outbound queue is full");
+                    self.pending_packets.pop_front();
+                    self.config.metrics.dropped(DropReason::HandlerQueueFull, 1);
+                }
+
+                self.pending_packets.push_back(packet);
+            }
+            Command::Reconfigure(config) => {
+                self.config = config;
+                self.outbound = None;
+                self.outbound_requested = false;
+            }
+            Command::Admit(admitted) => {
+                self.admitted = admitted;
+                self.outbound = None;
+                self.outbound_requested = false;
+                self.outbound_refused = false;
+                if !admitted {
+                    self.inbound = None;
+                    self.tunnel = None;
+                    self.remote_public_key = None;
+                    self.config
+                        .metrics
+                        .dropped(DropReason::NotConnected, self.pending_packets.len());
+                    self.pending_packets.clear();
+                    self.pending_datagrams.clear();
+                }
+            }
+        }
     }
 
     fn on_connection_event(
         &mut self,
-        event: libp2p::swarm::handler::ConnectionEvent<
+        event: ConnectionEvent<
             Self::InboundProtocol,
             Self::OutboundProtocol,
             Self::InboundOpenInfo,
             Self::OutboundOpenInfo,
         >,
     ) {
-        trace!("Handler::on_connection_event, {:?}", event);
-
         match event {
-            ConnectionEvent::FullyNegotiatedInbound(fully_negotiated_inbound) => {
-                trace!("{:?}", fully_negotiated_inbound)
-            }
-            ConnectionEvent::FullyNegotiatedOutbound(fully_negotiated_outbound) => {
-                trace!("{:?}", fully_negotiated_outbound)
-            }
-            ConnectionEvent::AddressChange(address_change) => {
-                trace!("{:?}", address_change)
+            // Negotiated before the remote was expelled.
+            ConnectionEvent::FullyNegotiatedInbound(_)
+            | ConnectionEvent::FullyNegotiatedOutbound(_)
+                if !self.admitted =>
+            {
+                trace!("Handler, dropping stream of expelled remote");
             }
-            ConnectionEvent::DialUpgradeError(dial_upgrade_error) => {
-                trace!("{:?}", dial_upgrade_error)
+            ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound {
+                protocol: (stream, hello),
+                ..
+            }) => {
+                trace!("Handler, inbound stream negotiated");
+                self.on_remote_hello(&hello);
+                if self.inbound.is_some() {
+                    trace!("Handler, replacing previous inbound stream");
+                }
+                self.inbound = Some(recv(stream));
+                self.pending_events.push_back(Event::Updated(hello));
+                // The remote admits us now, negotiate the outbound stream it refused.
+                if self.outbound_refused {
+                    self.outbound_refused = false;
+                    self.outbound_requested = false;
+                }
             }
-            ConnectionEvent::ListenUpgradeError(listen_upgrade_error) => {
-                trace!("{:?}", listen_upgrade_error)
+            ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound {
+                protocol: (stream, hello),
+                ..
+            }) => {
+                trace!("Handler, outbound stream negotiated");
+                self.on_remote_hello(&hello);
+                self.outbound = Some(OutboundState::Idle(stream));
+                self.pending_events.push_back(Event::Established(hello));
             }
-            ConnectionEvent::LocalProtocolsChange(protocols_change) => {
-                trace!("{:?}", protocols_change)
+            ConnectionEvent::DialUpgradeError(DialUpgradeError { error, .. }) => {
+                // Keep `outbound_requested` set, retrying will not change the outcome.
+                match error {
+                    StreamUpgradeError::Apply(error) => {
+                        self.pending_events.push_back(Event::HandshakeFailed(error))
+                    }
+                    StreamUpgradeError::NegotiationFailed => {
+                        trace!("Remote does not admit us to the VPN");
+                        self.outbound_refused = true;
+                    }
+                    error => warn!("Error negotiating outbound stream, {:?}", error),
+                }
             }
-            ConnectionEvent::RemoteProtocolsChange(protocols_change) => {
-                trace!("{:?}", protocols_change)
+            ConnectionEvent::ListenUpgradeError(ListenUpgradeError { error, .. }) => {
+                self.pending_events.push_back(Event::HandshakeFailed(error));
             }
             _ => {
                 trace!("{:?}", event);
@@ -111,23 +314,91 @@ impl ConnectionHandler for Handler {
             Self::ToBehaviour,
         >,
     > {
-        trace!("Handler::poll, {:?}", cx);
-        let mut shared_state = self.shared_state.lock().unwrap();
-
-        if !self.is_upgraded {
-            trace!("Starting protocol-upgrade ...");
-            self.is_upgraded = true;
-            // return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
-            //     protocol: SubstreamProtocol::new(Upgrade::new(), ()),
-            // });
-            let mut protocol = HashSet::new();
-            protocol.insert(VPN_PROTOCOL);
-            return Poll::Ready(ConnectionHandlerEvent::ReportRemoteProtocols(
-                ProtocolSupport::Added(protocol),
-            ));
+        if self.admitted && self.outbound.is_none() && !self.outbound_requested {
+            trace!("Requesting outbound stream ...");
+            self.outbound_requested = true;
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(self.upgrade(), ()),
+            });
+        }
+
+        while self.timer.poll_tick(cx).is_ready() {
+            if let Some(tunnel) = self.tunnel.as_mut() {
+                let output = tunnel.update_timers();
+                self.on_tunnel_output(output);
+            }
+        }
+
+        if self.tunnel.is_some() {
+            while let Some(packet) = self.pending_packets.pop_front() {
+                if let Some(tunnel) = self.tunnel.as_mut() {
+                    let output = tunnel.encapsulate(&packet);
+                    self.sent.0 += 1;
+                    self.sent.1 += packet.len() as u64;
+                    self.on_tunnel_output(output);
+                }
+            }
+        }
+
+        if self.sent != (0, 0) {
+            let (packets, bytes) = std::mem::take(&mut self.sent);
+            self.pending_events
+                .push_back(Event::Sent { packets, bytes });
+        }
+
+        while let Some(mut future) = self.inbound.take() {
+            match future.poll_unpin(cx) {
+                Poll::Ready(Ok((stream, datagram))) => {
+                    self.inbound = Some(recv(stream));
+                    if let Some(tunnel) = self.tunnel.as_mut() {
+                        let output = tunnel.decapsulate(&datagram);
+                        self.on_tunnel_output(output);
+                    }
+                }
+                Poll::Ready(Err(e)) => {
+                    // The remote negotiates a new inbound stream when it has more to send.
+                    trace!("Inbound stream closed, {e}");
+                }
+                Poll::Pending => {
+                    self.inbound = Some(future);
+                    break;
+                }
+            }
+        }
+
+        loop {
+            match self.outbound.take() {
+                Some(OutboundState::Idle(stream)) => match self.pending_datagrams.pop_front() {
+                    Some(datagram) => {
+                        self.outbound = Some(OutboundState::Sending(send(stream, datagram)))
+                    }
+                    None => {
+                        self.outbound = Some(OutboundState::Idle(stream));
+                        break;
+                    }
+                },
+                Some(OutboundState::Sending(mut future)) => match future.poll_unpin(cx) {
+                    Poll::Ready(Ok(stream)) => self.outbound = Some(OutboundState::Idle(stream)),
+                    Poll::Ready(Err(e)) => {
+                        warn!("Error sending datagram, {e}");
+                        // Negotiate a fresh stream the next time we are polled.
+                        self.outbound_requested = false;
+                        self.pending_events.push_back(Event::Closed);
+                        break;
+                    }
+                    Poll::Pending => {
+                        self.outbound = Some(OutboundState::Sending(future));
+                        break;
+                    }
+                },
+                None => break,
+            }
+        }
+
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(event));
         }
 
-        shared_state.waker = Some(cx.waker().clone());
         Poll::Pending
     }
 }
@@ -0,0 +1,208 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+use super::upgrade::Error;
+use crate::network::NetworkKey;
+use libp2p::PeerId;
+
+/// Version of the handshake and framing on the VPN-stream, peers must agree on it.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Payload-encryption of the stream with a WireGuard-session.
+pub const FEATURE_WIREGUARD: &str = "wireguard";
+
+/// Features a remote has to support for the stream to be usable.
+const REQUIRED_FEATURES: [&str; 1] = [FEATURE_WIREGUARD];
+
+/// The message both sides send at the start of each VPN-stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: u16,
+    /// The vpnr release of the sender, informational only.
+    pub software: String,
+    /// The sender's WireGuard public key.
+    pub public_key: [u8; 32],
+    /// The sender's overlay IP-addresses.
+    pub addresses: Vec<IpAddr>,
+    /// Whether the addresses are derived from the sender's PeerId, those yield on conflicts.
+    #[serde(default)]
+    pub derived_addresses: bool,
+    pub mtu: u16,
+    /// Subnets reachable through the sender.
+    pub routes: Vec<IpNet>,
+    pub features: BTreeSet<String>,
+    /// Proof the sender holds the key of the private network, bound to both PeerIds.
+    #[serde(default)]
+    pub membership_proof: Option<[u8; 32]>,
+}
+
+impl Hello {
+    pub fn new(public_key: [u8; 32], addresses: Vec<IpAddr>, mtu: u16, routes: Vec<IpNet>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            software: env!("CARGO_PKG_VERSION").to_string(),
+            public_key,
+            addresses,
+            derived_addresses: false,
+            mtu,
+            routes,
+            features: BTreeSet::from([FEATURE_WIREGUARD.to_string()]),
+            membership_proof: None,
+        }
+    }
+
+    /// Check that `remote` can share an overlay with the local node.
+    pub fn validate(&self, remote: &Hello) -> Result<(), Error> {
+        if remote.version != self.version {
+            return Err(Error::IncompatibleVersion {
+                local: self.version,
+                remote: remote.version,
+            });
+        }
+
+        if let Some(feature) = REQUIRED_FEATURES
+            .iter()
+            .find(|feature| !remote.features.contains(**feature))
+        {
+            return Err(Error::MissingFeature(feature.to_string()));
+        }
+
+        if remote.mtu != self.mtu {
+            return Err(Error::MtuMismatch {
+                local: self.mtu,
+                remote: remote.mtu,
+            });
+        }
+
+        if let Some(address) = remote
+            .addresses
+            .iter()
+            .find(|address| self.addresses.contains(address))
+        {
+            return Err(Error::AddressConflict {
+                address: *address,
+                remote_derived: remote.derived_addresses,
+            });
+        }
+
+        // Overlapping routes would split the subnet's traffic between both nodes.
+        if let Some(route) = remote.routes.iter().find(|route| {
+            self.routes
+                .iter()
+                .any(|local| local.contains(*route) || route.contains(local))
+        }) {
+            return Err(Error::RouteConflict(*route));
+        }
+
+        Ok(())
+    }
+}
+
+/// Verifies a remote's membership in the private network.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    pub network: NetworkKey,
+    pub local: PeerId,
+    pub remote: PeerId,
+}
+
+impl Membership {
+    pub fn verify(&self, remote: &Hello) -> Result<(), Error> {
+        match &remote.membership_proof {
+            Some(proof)
+                if self
+                    .network
+                    .verify_membership(proof, &self.remote, &self.local) =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::NotAMember),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(addresses: &[&str], routes: &[&str]) -> Hello {
+        Hello::new(
+            [0; 32],
+            addresses.iter().map(|a| a.parse().unwrap()).collect(),
+            1420,
+            routes.iter().map(|r| r.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn accepts_disjoint_remote() {
+        let local = hello(&["10.0.0.1"], &["192.168.1.0/24"]);
+        let remote = hello(&["10.0.0.2"], &["192.168.2.0/24"]);
+        assert!(local.validate(&remote).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_version() {
+        let local = hello(&["10.0.0.1"], &[]);
+        let mut remote = hello(&["10.0.0.2"], &[]);
+        remote.version += 1;
+        assert!(matches!(
+            local.validate(&remote),
+            Err(Error::IncompatibleVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_feature() {
+        let local = hello(&["10.0.0.1"], &[]);
+        let mut remote = hello(&["10.0.0.2"], &[]);
+        remote.features.clear();
+        assert!(matches!(
+            local.validate(&remote),
+            Err(Error::MissingFeature(feature)) if feature == FEATURE_WIREGUARD
+        ));
+    }
+
+    #[test]
+    fn rejects_other_mtu() {
+        let local = hello(&["10.0.0.1"], &[]);
+        let mut remote = hello(&["10.0.0.2"], &[]);
+        remote.mtu = 1280;
+        assert!(matches!(
+            local.validate(&remote),
+            Err(Error::MtuMismatch {
+                local: 1420,
+                remote: 1280
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_local_address() {
+        let local = hello(&["10.0.0.1"], &[]);
+        let mut remote = hello(&["10.0.0.2", "10.0.0.1"], &[]);
+        remote.derived_addresses = true;
+        assert!(matches!(
+            local.validate(&remote),
+            Err(Error::AddressConflict {
+                remote_derived: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_overlapping_routes() {
+        let local = hello(&["10.0.0.1"], &["192.168.0.0/16"]);
+
+        for route in ["192.168.0.0/16", "192.168.1.0/24", "192.0.0.0/8"] {
+            let remote = hello(&["10.0.0.2"], &[route]);
+            assert!(
+                matches!(local.validate(&remote), Err(Error::RouteConflict(_))),
+                "{route}"
+            );
+        }
+    }
+}
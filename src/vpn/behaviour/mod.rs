@@ -1,43 +1,559 @@
 mod handler;
+mod handshake;
+mod protocol;
 mod upgrade;
 
-use super::config::Config;
-use handler::Handler;
+pub use handshake::Hello;
+pub use upgrade::Error as HandshakeError;
+
+use super::config::{Config, Peer};
+use super::routing::RoutingTable;
+use super::{address, packet};
+use crate::metrics::DropReason;
+use crate::network::Members;
+use handler::{Command, Handler};
+use ipnet::IpNet;
 use libp2p::{
     Multiaddr, PeerId,
     core::Endpoint,
-    swarm::{ConnectionDenied, ConnectionId, NetworkBehaviour, THandlerInEvent, ToSwarm},
+    swarm::{
+        CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionEstablished, ConnectionId,
+        DialError, DialFailure, FromSwarm, NetworkBehaviour, NotifyHandler, THandlerInEvent,
+        ToSwarm,
+        dial_opts::{DialOpts, PeerCondition},
+    },
 };
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
 use std::task::{Context, Poll};
-use tracing::trace;
+use std::time::{Duration, Instant};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{debug, info, trace, warn};
 
-#[derive(Debug)]
-pub enum Event {
-    TestEvent,
-    VpnEstablishedEvent,
+/// Delay before redialing a static peer, doubled after each failed dial.
+const REDIAL_DELAY_MIN: Duration = Duration::from_secs(1);
+const REDIAL_DELAY_MAX: Duration = Duration::from_secs(60);
+
+/// Packets queued per destination while its owner is resolved and dialed.
+const MAX_UNROUTED_PACKETS: usize = 16;
+
+/// Time to resolve and connect the owner of a destination before its packets are dropped.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Packets and bytes exchanged with a peer through the tunnel.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Traffic {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
 }
 
-pub struct TestEvent {}
-pub struct VpnEstablishedEvent {
-    pub peer_id: PeerId,
+#[derive(Debug)]
+pub enum Event {
+    /// A VPN-stream to `peer_id` was established.
+    Established {
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        hello: Hello,
+    },
+    /// The handshake with `peer_id` failed, the connection is closed.
+    HandshakeFailed {
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        error: HandshakeError,
+    },
+    /// An IP-packet was received from `peer_id`.
+    Packet { peer_id: PeerId, packet: Vec<u8> },
+    /// No peer routes `destination`, its packets are queued until the owner is resolved.
+    Unresolved { destination: IpAddr },
+    /// The derived overlay-address `old` conflicted with a peer's and was replaced by `new`.
+    AddressChanged { old: IpAddr, new: IpAddr },
 }
 
 pub struct Behaviour {
     config: Config,
     peers: HashSet<PeerId>,
+    /// The connection of each peer its packets are sent on, one of its tunnels.
+    established: HashMap<PeerId, ConnectionId>,
+    /// Connections with a negotiated VPN-stream, by peer.
+    tunnels: HashMap<PeerId, Vec<ConnectionId>>,
+    /// All connections, by peer, to admit and expel peers at runtime.
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    /// The peers admitted to the VPN, `None` admits every peer.
+    allowed: Option<HashSet<PeerId>>,
+    /// The handshake-messages received from the peers.
+    remotes: HashMap<PeerId, Hello>,
+    /// The peers with an established VPN-stream, shared with other behaviours.
+    members: Members,
+    /// The traffic of the connected peers.
+    traffic: HashMap<PeerId, Traffic>,
+    /// Owners of the overlay-addresses and subnets, for delivering outgoing packets.
+    routing: RoutingTable,
+    /// Packets without a route, queued while their owner is resolved.
+    unrouted: HashMap<IpAddr, (Instant, VecDeque<Vec<u8>>)>,
+    /// Static peers waiting to be redialed.
+    redials: HashMap<PeerId, Instant>,
+    /// Current redial-delay of static peers, reset once connected.
+    redial_delays: HashMap<PeerId, Duration>,
+    redial_timer: Interval,
+    /// Salt of the derived overlay-address, raised on every conflict.
+    address_salt: u32,
+    pending_events: VecDeque<ToSwarm<Event, Command>>,
 }
 
 impl Behaviour {
     pub fn new(config: Config) -> Self {
+        let mut redial_timer = tokio::time::interval(REDIAL_DELAY_MIN);
+        redial_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let pending_events = config.peers.keys().map(|peer_id| dial(*peer_id)).collect();
+
         Self {
             config,
             peers: HashSet::new(),
+            established: HashMap::new(),
+            tunnels: HashMap::new(),
+            connections: HashMap::new(),
+            allowed: None,
+            remotes: HashMap::new(),
+            members: Members::default(),
+            traffic: HashMap::new(),
+            routing: RoutingTable::default(),
+            unrouted: HashMap::new(),
+            redials: HashMap::new(),
+            redial_delays: HashMap::new(),
+            redial_timer,
+            address_salt: 0,
+            pending_events,
+        }
+    }
+
+    /// Replace the static peers, dialing the new ones. Connections to peers no longer
+    /// configured are kept, but not redialed.
+    pub fn set_peers(&mut self, peers: HashMap<PeerId, Peer>) {
+        for peer_id in peers.keys() {
+            if !self.config.peers.contains_key(peer_id) && !self.established.contains_key(peer_id) {
+                self.pending_events.push_back(dial(*peer_id));
+            }
+        }
+        self.redials
+            .retain(|peer_id, _| peers.contains_key(peer_id));
+        self.redial_delays
+            .retain(|peer_id, _| peers.contains_key(peer_id));
+        self.config.peers = peers;
+
+        // The `allowed_ips` of the connected peers may have changed.
+        let connected: Vec<PeerId> = self.remotes.keys().copied().collect();
+        for peer_id in connected {
+            self.update_routes(peer_id);
         }
     }
+
     pub fn add_peer(&mut self, peer_id: PeerId) -> bool {
         self.peers.insert(peer_id)
     }
+
+    /// The handshake-message `peer_id` sent on its VPN-stream.
+    pub fn remote(&self, peer_id: &PeerId) -> Option<&Hello> {
+        self.remotes.get(peer_id)
+    }
+
+    /// The local overlay-addresses.
+    pub fn addresses(&self) -> &[IpAddr] {
+        &self.config.addresses
+    }
+
+    /// The subnets advertised as reachable through this node.
+    pub fn advertised_routes(&self) -> &[IpNet] {
+        &self.config.routes
+    }
+
+    /// Whether `peer_id` belongs to the network: if admitted, in a private network once it
+    /// proved its membership in the handshake.
+    pub fn is_member(&self, peer_id: &PeerId) -> bool {
+        self.admits(peer_id) && (self.config.network.is_none() || self.members.contains(peer_id))
+    }
+
+    /// Whether `peer_id` may negotiate a VPN-stream.
+    pub fn admits(&self, peer_id: &PeerId) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(peer_id))
+    }
+
+    /// Admit only the `allowed` peers to the VPN, or every peer if `None`. The tunnels of the
+    /// connected peers no longer admitted are closed, their connections are kept.
+    pub fn set_allowed(&mut self, allowed: Option<HashSet<PeerId>>) {
+        let before: HashSet<PeerId> = self
+            .connections
+            .keys()
+            .filter(|peer_id| self.admits(peer_id))
+            .copied()
+            .collect();
+        self.allowed = allowed;
+
+        let connections: Vec<(PeerId, Vec<ConnectionId>)> = self
+            .connections
+            .iter()
+            .map(|(peer_id, connections)| (*peer_id, connections.clone()))
+            .collect();
+        for (peer_id, connections) in connections {
+            let admitted = self.admits(&peer_id);
+            if admitted == before.contains(&peer_id) {
+                continue;
+            }
+
+            match admitted {
+                true => info!("Admitting {} to the VPN", peer_id),
+                false => {
+                    info!("Expelling {} from the VPN", peer_id);
+                    self.expel(peer_id);
+                }
+            }
+            for connection_id in connections {
+                self.pending_events.push_back(ToSwarm::NotifyHandler {
+                    peer_id,
+                    handler: NotifyHandler::One(connection_id),
+                    event: Command::Admit(admitted),
+                });
+            }
+        }
+    }
+
+    /// Forget the tunnels and routes of `peer_id`.
+    fn expel(&mut self, peer_id: PeerId) {
+        for connection_id in self.tunnels.get(&peer_id).cloned().unwrap_or_default() {
+            self.close_tunnel(peer_id, connection_id);
+        }
+        self.remotes.remove(&peer_id);
+        self.members.remove(&peer_id);
+        self.routing.remove_peer(&peer_id);
+    }
+
+    /// Handle on the peers with an established VPN-stream, in a private network the ones which
+    /// proved their membership.
+    pub fn members(&self) -> Members {
+        self.members.clone()
+    }
+
+    /// The traffic exchanged with `peer_id` since it connected.
+    pub fn traffic(&self, peer_id: &PeerId) -> Traffic {
+        self.traffic.get(peer_id).copied().unwrap_or_default()
+    }
+
+    pub fn routing_table(&self) -> &RoutingTable {
+        &self.routing
+    }
+
+    /// Route to `peer_id` what it may send from: the `allowed_ips` of a static peer, otherwise
+    /// the addresses and routes of its handshake.
+    fn update_routes(&mut self, peer_id: PeerId) {
+        let routes = match self.config.peers.get(&peer_id) {
+            Some(peer) if !peer.allowed_ips.is_empty() => peer.allowed_ips.clone(),
+            _ => match self.remotes.get(&peer_id) {
+                Some(hello) => hello
+                    .addresses
+                    .iter()
+                    .map(|address| IpNet::from(*address))
+                    .chain(hello.routes.iter().copied())
+                    .collect(),
+                None => vec![],
+            },
+        };
+
+        debug!("Routes of {}, {:?}", peer_id, routes);
+        self.routing.set_peer(peer_id, routes);
+        self.flush_unrouted();
+    }
+
+    /// Send the queued packets whose destination is now routed to a connected peer.
+    fn flush_unrouted(&mut self) {
+        let routed: Vec<(IpAddr, PeerId, ConnectionId)> = self
+            .unrouted
+            .keys()
+            .filter_map(|destination| {
+                let peer_id = self.routing.lookup(*destination)?;
+                let connection_id = self.established.get(&peer_id)?;
+                Some((*destination, peer_id, *connection_id))
+            })
+            .collect();
+
+        for (destination, peer_id, connection_id) in routed {
+            let Some((_, packets)) = self.unrouted.remove(&destination) else {
+                continue;
+            };
+            debug!(
+                "Sending {} queued packets to {}",
+                packets.len(),
+                destination
+            );
+            for packet in packets {
+                self.deliver(peer_id, connection_id, packet);
+            }
+        }
+    }
+
+    /// Send `packet` through the tunnel on `connection_id`.
+    fn deliver(&mut self, peer_id: PeerId, connection_id: ConnectionId, packet: Vec<u8>) {
+        self.pending_events.push_back(ToSwarm::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::One(connection_id),
+            event: Command::SendPacket(packet),
+        });
+    }
+
+    /// `peer_id` owns `destination` according to the DHT, dial it to deliver the queued packets.
+    pub fn resolved(&mut self, destination: IpAddr, peer_id: PeerId, addresses: Vec<Multiaddr>) {
+        if !self.unrouted.contains_key(&destination) {
+            return;
+        }
+
+        if self.established.contains_key(&peer_id) {
+            debug!(
+                "Resolved {} to connected {}, which does not route it",
+                destination, peer_id
+            );
+            if let Some((_, packets)) = self.unrouted.remove(&destination) {
+                self.config
+                    .metrics
+                    .dropped(DropReason::Unresolved, packets.len());
+            }
+            return;
+        }
+
+        debug!("Resolved {} to {}, dialing", destination, peer_id);
+        self.pending_events.push_back(ToSwarm::Dial {
+            opts: DialOpts::peer_id(peer_id)
+                .addresses(addresses)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build(),
+        });
+    }
+
+    /// The owner of `destination` could not be resolved, drop its queued packets.
+    pub fn unresolved(&mut self, destination: IpAddr) {
+        if let Some((_, packets)) = self.unrouted.remove(&destination) {
+            debug!(
+                "Dropping {} packets, {} is unknown",
+                packets.len(),
+                destination
+            );
+            self.config
+                .metrics
+                .dropped(DropReason::Unresolved, packets.len());
+        }
+    }
+
+    /// Whether `peer_id` may send `packet`, judged by its source-address.
+    fn allows_source(&self, peer_id: &PeerId, packet: &[u8]) -> bool {
+        let Some(source) = packet::source(packet) else {
+            return false;
+        };
+
+        match self.config.peers.get(peer_id) {
+            Some(peer) if !peer.allowed_ips.is_empty() => {
+                peer.allowed_ips.iter().any(|net| net.contains(&source))
+            }
+            _ => self.remotes.get(peer_id).is_some_and(|hello| {
+                hello.addresses.contains(&source)
+                    || hello.routes.iter().any(|net| net.contains(&source))
+            }),
+        }
+    }
+
+    /// Whether `address` is a host-address of the overlay-network.
+    fn in_overlay(&self, address: IpAddr) -> bool {
+        self.config.network_prefix.is_none_or(|prefix| {
+            prefix.contains(&address) && !(address.is_ipv4() && address == prefix.broadcast())
+        })
+    }
+
+    /// The VPN-stream on `connection_id` ended, send on another tunnel to `peer_id` if any.
+    fn close_tunnel(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        let Some(tunnels) = self.tunnels.get_mut(&peer_id) else {
+            return;
+        };
+        tunnels.retain(|tunnel| *tunnel != connection_id);
+
+        let remaining = tunnels.last().copied();
+        if tunnels.is_empty() {
+            self.tunnels.remove(&peer_id);
+        }
+        if self.established.get(&peer_id) == Some(&connection_id) {
+            match remaining {
+                Some(tunnel) => self.established.insert(peer_id, tunnel),
+                None => self.established.remove(&peer_id),
+            };
+            self.config.metrics.set_tunnels(self.established.len());
+        }
+    }
+
+    /// Send to each peer with several tunnels on the one with the lowest round-trip-time, as
+    /// `rtt` measures them. Unmeasured tunnels are not chosen.
+    pub fn choose_tunnels(&mut self, rtt: impl Fn(&ConnectionId) -> Option<Duration>) {
+        for (peer_id, tunnels) in self.tunnels.iter() {
+            let fastest = tunnels
+                .iter()
+                .filter_map(|tunnel| Some((rtt(tunnel)?, *tunnel)))
+                .min_by_key(|(rtt, _)| *rtt);
+            if let Some((rtt, tunnel)) = fastest
+                && self.established.insert(*peer_id, tunnel) != Some(tunnel)
+            {
+                debug!("Sending to {} on {}, rtt {:?}", peer_id, tunnel, rtt);
+            }
+        }
+    }
+
+    /// Schedule a redial of a static peer after `delay`.
+    fn schedule_redial(&mut self, peer_id: PeerId, delay: Duration) {
+        debug!("Redialing static peer {} in {:?}", peer_id, delay);
+        self.redial_delays.insert(peer_id, delay);
+        self.redials.insert(peer_id, Instant::now() + delay);
+    }
+
+    fn new_handler(&mut self, connection_id: ConnectionId, peer: &PeerId) -> Handler {
+        self.connections
+            .entry(*peer)
+            .or_default()
+            .push(connection_id);
+        Handler::new(self.handler_config(peer), *peer, self.admits(peer))
+    }
+
+    fn handler_config(&self, peer: &PeerId) -> Config {
+        let mut config = self.config.clone();
+        if let Some(keepalive) = self
+            .config
+            .peers
+            .get(peer)
+            .and_then(|peer| peer.persistent_keepalive)
+        {
+            config.persistent_keepalive = Some(keepalive);
+        }
+
+        config
+    }
+
+    /// Whether the local address yields to `peer_id` on a conflict: derived addresses yield to
+    /// configured ones, between derived addresses the higher PeerId yields.
+    fn yields_address(&self, peer_id: &PeerId, remote_derived: bool) -> bool {
+        self.config.address_prefix.is_some()
+            && (!remote_derived || self.config.local_peer_id > *peer_id)
+    }
+
+    /// Derive the next overlay-address in place of `conflict`, announce it to the connected
+    /// peers and redial `peer_id`.
+    fn reassign_address(&mut self, conflict: IpAddr, peer_id: PeerId) {
+        let Some(prefix) = self.config.address_prefix else {
+            return;
+        };
+        if !self.config.addresses.contains(&conflict) {
+            // Already reassigned by an earlier handshake.
+            return;
+        }
+
+        let taken: HashSet<IpAddr> = self
+            .remotes
+            .values()
+            .flat_map(|hello| hello.addresses.iter().copied())
+            .chain([conflict])
+            .collect();
+
+        let Some((new, salt)) = address::derive_free(
+            &self.config.local_peer_id,
+            &prefix,
+            self.address_salt,
+            &taken,
+        ) else {
+            warn!(
+                "No free overlay-address in {}, keeping {}",
+                prefix, conflict
+            );
+            return;
+        };
+        self.address_salt = salt;
+
+        for address in self.config.addresses.iter_mut() {
+            if *address == conflict {
+                *address = new;
+            }
+        }
+
+        self.pending_events
+            .push_back(ToSwarm::GenerateEvent(Event::AddressChanged {
+                old: conflict,
+                new,
+            }));
+
+        let tunnels: Vec<(PeerId, ConnectionId)> = self
+            .tunnels
+            .iter()
+            .flat_map(|(peer_id, connections)| {
+                connections
+                    .iter()
+                    .map(|connection_id| (*peer_id, *connection_id))
+            })
+            .collect();
+        for (peer, connection_id) in tunnels {
+            self.pending_events.push_back(ToSwarm::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::One(connection_id),
+                event: Command::Reconfigure(self.handler_config(&peer)),
+            });
+        }
+
+        self.schedule_redial(peer_id, REDIAL_DELAY_MIN);
+    }
+
+    /// Queue an IP-packet read from the local interface for delivery to the peer routing its
+    /// destination.
+    pub fn send_packet(&mut self, packet: Vec<u8>) {
+        trace!("Behaviour::send_packet, {} bytes", packet.len());
+
+        let Some(destination) = packet::destination(&packet) else {
+            debug!("Dropping packet, not an IP-packet");
+            self.config.metrics.dropped(DropReason::NotIp, 1);
+            return;
+        };
+
+        if !packet::is_unicast(destination) {
+            debug!("Dropping packet, {} is no unicast-address", destination);
+            self.config.metrics.dropped(DropReason::Unroutable, 1);
+            return;
+        }
+
+        let Some(peer_id) = self.routing.lookup(destination) else {
+            // Only overlay-addresses are published, any other destination can not be resolved.
+            if !self.in_overlay(destination) {
+                debug!("Dropping packet, {} is not routed", destination);
+                self.config.metrics.dropped(DropReason::Unroutable, 1);
+                return;
+            }
+            let (_, packets) = self.unrouted.entry(destination).or_insert_with(|| {
+                self.pending_events
+                    .push_back(ToSwarm::GenerateEvent(Event::Unresolved { destination }));
+                (Instant::now(), VecDeque::new())
+            });
+            if packets.len() >= MAX_UNROUTED_PACKETS {
+                packets.pop_front();
+                self.config.metrics.dropped(DropReason::QueueFull, 1);
+            }
+            packets.push_back(packet);
+            return;
+        };
+
+        match self.established.get(&peer_id) {
+            Some(connection_id) => self.deliver(peer_id, *connection_id, packet),
+            None => {
+                debug!(
+                    "Dropping packet to {}, {} is not connected",
+                    destination, peer_id
+                );
+                self.config.metrics.dropped(DropReason::NotConnected, 1);
+            }
+        }
+    }
 }
 
 impl NetworkBehaviour for Behaviour {
@@ -47,6 +563,53 @@ impl NetworkBehaviour for Behaviour {
     fn on_swarm_event(&mut self, event: libp2p::swarm::FromSwarm) {
         trace!("Behaviour::on_swarm_event, {:?}", event);
         match event {
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                remaining_established,
+                ..
+            }) => {
+                self.close_tunnel(peer_id, connection_id);
+                if let Some(connections) = self.connections.get_mut(&peer_id) {
+                    connections.retain(|connection| *connection != connection_id);
+                    if connections.is_empty() {
+                        self.connections.remove(&peer_id);
+                    }
+                }
+                if remaining_established == 0 {
+                    self.peers.remove(&peer_id);
+                    self.remotes.remove(&peer_id);
+                    self.members.remove(&peer_id);
+                    self.traffic.remove(&peer_id);
+                    self.config.metrics.remove_peer(&peer_id);
+                    self.routing.remove_peer(&peer_id);
+
+                    if self.config.peers.contains_key(&peer_id) {
+                        self.schedule_redial(peer_id, REDIAL_DELAY_MIN);
+                    }
+                }
+            }
+            FromSwarm::ConnectionEstablished(ConnectionEstablished { peer_id, .. }) => {
+                self.redials.remove(&peer_id);
+                self.redial_delays.remove(&peer_id);
+            }
+            FromSwarm::DialFailure(DialFailure {
+                peer_id: Some(peer_id),
+                error,
+                ..
+            }) => {
+                if self.config.peers.contains_key(&peer_id)
+                    && !self.peers.contains(&peer_id)
+                    && !matches!(error, DialError::DialPeerConditionFalse(_))
+                {
+                    let delay = self
+                        .redial_delays
+                        .get(&peer_id)
+                        .map_or(REDIAL_DELAY_MIN, |delay| *delay * 2)
+                        .min(REDIAL_DELAY_MAX);
+                    self.schedule_redial(peer_id, delay);
+                }
+            }
             _ => {
                 trace!("vpn::Behaviour::on_swarm_event")
             }
@@ -58,6 +621,40 @@ impl NetworkBehaviour for Behaviour {
         cx: &mut Context<'_>,
     ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
         trace!("Behaviour::poll, {:?}", cx);
+
+        while self.redial_timer.poll_tick(cx).is_ready() {
+            let now = Instant::now();
+            let due: Vec<PeerId> = self
+                .redials
+                .iter()
+                .filter(|(_, at)| **at <= now)
+                .map(|(peer_id, _)| *peer_id)
+                .collect();
+
+            for peer_id in due {
+                self.redials.remove(&peer_id);
+                self.pending_events.push_back(dial(peer_id));
+            }
+
+            let metrics = &self.config.metrics;
+            self.unrouted.retain(|destination, (since, packets)| {
+                let keep = now.duration_since(*since) < RESOLVE_TIMEOUT;
+                if !keep {
+                    debug!(
+                        "Dropping {} packets, {} not reached in time",
+                        packets.len(),
+                        destination
+                    );
+                    metrics.dropped(DropReason::Unresolved, packets.len());
+                }
+                keep
+            });
+        }
+
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(event);
+        }
+
         Poll::Pending
     }
 
@@ -71,6 +668,87 @@ impl NetworkBehaviour for Behaviour {
             "Behaviour::on_connection_handler_event, {}, {}, {:?}",
             peer_id, connection_id, event
         );
+
+        // Streams negotiated before the handler learned the peer was expelled.
+        if !self.admits(&peer_id)
+            && matches!(
+                event,
+                handler::Event::Established(_)
+                    | handler::Event::Updated(_)
+                    | handler::Event::Packet(_)
+            )
+        {
+            debug!("Ignoring VPN-stream of {}, not admitted", peer_id);
+            return;
+        }
+
+        match event {
+            handler::Event::Established(hello) => {
+                // The newest tunnel is used until the latencies tell otherwise.
+                let tunnels = self.tunnels.entry(peer_id).or_default();
+                if !tunnels.contains(&connection_id) {
+                    tunnels.push(connection_id);
+                }
+                self.established.insert(peer_id, connection_id);
+                self.config.metrics.set_tunnels(self.established.len());
+                self.remotes.insert(peer_id, hello.clone());
+                self.members.insert(peer_id);
+                self.update_routes(peer_id);
+                self.pending_events
+                    .push_back(ToSwarm::GenerateEvent(Event::Established {
+                        peer_id,
+                        connection_id,
+                        hello,
+                    }));
+            }
+            handler::Event::Updated(hello) => {
+                self.remotes.insert(peer_id, hello);
+                self.update_routes(peer_id);
+            }
+            handler::Event::HandshakeFailed(error) => {
+                warn!("Handshake with {} failed, {}", peer_id, error);
+                self.config.metrics.handshake_failed();
+                if let HandshakeError::AddressConflict {
+                    address,
+                    remote_derived,
+                } = error
+                    && self.yields_address(&peer_id, remote_derived)
+                {
+                    self.reassign_address(address, peer_id);
+                }
+                self.pending_events.push_back(ToSwarm::CloseConnection {
+                    peer_id,
+                    connection: CloseConnection::One(connection_id),
+                });
+                self.pending_events
+                    .push_back(ToSwarm::GenerateEvent(Event::HandshakeFailed {
+                        peer_id,
+                        connection_id,
+                        error,
+                    }));
+            }
+            handler::Event::Packet(packet) => {
+                if !self.allows_source(&peer_id, &packet) {
+                    debug!("Dropping packet from {}, source not allowed", peer_id);
+                    self.config.metrics.dropped(DropReason::SourceNotAllowed, 1);
+                    return;
+                }
+                let traffic = self.traffic.entry(peer_id).or_default();
+                traffic.rx_packets += 1;
+                traffic.rx_bytes += packet.len() as u64;
+                self.config.metrics.received(&peer_id, packet.len());
+
+                self.pending_events
+                    .push_back(ToSwarm::GenerateEvent(Event::Packet { peer_id, packet }));
+            }
+            handler::Event::Sent { packets, bytes } => {
+                let traffic = self.traffic.entry(peer_id).or_default();
+                traffic.tx_packets += packets;
+                traffic.tx_bytes += bytes;
+                self.config.metrics.sent(&peer_id, packets, bytes);
+            }
+            handler::Event::Closed => self.close_tunnel(peer_id, connection_id),
+        }
     }
 
     fn handle_pending_inbound_connection(
@@ -101,7 +779,10 @@ impl NetworkBehaviour for Behaviour {
         );
 
         // Err(ConnectionDenied::new("Because out ..."))
-        Ok(vec![])
+        Ok(maybe_peer
+            .and_then(|peer_id| self.config.peers.get(&peer_id))
+            .map(|peer| peer.addresses.clone())
+            .unwrap_or_default())
     }
 
     fn handle_established_inbound_connection(
@@ -119,7 +800,7 @@ impl NetworkBehaviour for Behaviour {
         self.add_peer(peer);
 
         // Err(ConnectionDenied::new("Because why in ..."))
-        Ok(Handler::default())
+        Ok(self.new_handler(connection_id, &peer))
     }
 
     fn handle_established_outbound_connection(
@@ -138,6 +819,14 @@ impl NetworkBehaviour for Behaviour {
         self.add_peer(peer);
 
         // Err(ConnectionDenied::new("Because why out ..."))
-        Ok(Handler::default())
+        Ok(self.new_handler(connection_id, &peer))
+    }
+}
+
+fn dial(peer_id: PeerId) -> ToSwarm<Event, Command> {
+    ToSwarm::Dial {
+        opts: DialOpts::peer_id(peer_id)
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .build(),
     }
 }
@@ -0,0 +1,36 @@
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+
+/// Largest IP-packet that fits into a frame, limited by the 2-byte length-prefix.
+pub const MAX_PACKET_SIZE: usize = u16::MAX as usize;
+
+/// Write `packet` to `stream`, prefixed with its length as big-endian `u16`.
+pub(crate) async fn send_packet<S>(stream: &mut S, packet: &[u8]) -> Result<(), io::Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    let length = u16::try_from(packet.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("packet of {} bytes exceeds frame size", packet.len()),
+        )
+    })?;
+
+    stream.write_all(&length.to_be_bytes()).await?;
+    stream.write_all(packet).await?;
+    stream.flush().await
+}
+
+/// Read the next length-prefixed packet from `stream`.
+pub(crate) async fn recv_packet<S>(stream: &mut S) -> Result<Vec<u8>, io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).await?;
+
+    let mut packet = vec![0u8; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut packet).await?;
+
+    Ok(packet)
+}
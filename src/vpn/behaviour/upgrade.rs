@@ -1,31 +1,122 @@
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use ipnet::IpNet;
 use libp2p::{InboundUpgrade, OutboundUpgrade, Stream, StreamProtocol, core::UpgradeInfo};
-use std::future::{Ready, ready};
+use std::net::IpAddr;
+use std::{error, fmt, io};
 use tracing::trace;
 
+use super::handshake::{Hello, Membership};
+use super::protocol;
+
 pub const VPN_PROTOCOL: StreamProtocol = StreamProtocol::new("/libp2p/vpn/0.0.1");
 
 #[derive(Debug)]
 pub struct Upgrade {
     pub(crate) supported_protocols: Vec<StreamProtocol>,
+    /// The local handshake-message, sent to the remote.
+    hello: Hello,
+    /// Verifies the remote in a private network.
+    membership: Option<Membership>,
 }
 
-struct HandshakeError {}
-
+/// Error negotiating a VPN-stream
 #[derive(Debug)]
 pub enum Error {
-    HandshakeError,
+    /// I/O error during handshake.
+    HandshakeError(io::Error),
+    /// The remote sent a handshake-message we can not decode.
+    Malformed(serde_json::Error),
+    /// The remote speaks a different protocol-version.
+    IncompatibleVersion { local: u16, remote: u16 },
+    /// The remote lacks a required feature.
+    MissingFeature(String),
+    /// The remote's interface uses a different MTU.
+    MtuMismatch { local: u16, remote: u16 },
+    /// The remote claims one of the local overlay-addresses.
+    AddressConflict {
+        address: IpAddr,
+        remote_derived: bool,
+    },
+    /// The remote advertises one of the local routes.
+    RouteConflict(IpNet),
+    /// The remote failed to prove it holds the key of the private network.
+    NotAMember,
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::HandshakeError(err)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::HandshakeError(err) => Some(err),
+            Error::Malformed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HandshakeError(e) => write!(f, "Handshake error: {e}"),
+            Error::Malformed(e) => write!(f, "Malformed handshake: {e}"),
+            Error::IncompatibleVersion { local, remote } => {
+                write!(
+                    f,
+                    "Incompatible protocol-version {remote}, expected {local}"
+                )
+            }
+            Error::MissingFeature(feature) => write!(f, "Remote lacks feature {feature}"),
+            Error::MtuMismatch { local, remote } => {
+                write!(f, "Remote MTU {remote} differs from local MTU {local}")
+            }
+            Error::AddressConflict { address, .. } => {
+                write!(f, "Remote claims local overlay-address {address}")
+            }
+            Error::RouteConflict(route) => write!(f, "Remote advertises local route {route}"),
+            Error::NotAMember => write!(f, "Remote is not a member of the private network"),
+        }
+    }
 }
 
 impl Upgrade {
-    pub fn new() -> Self {
+    pub fn new(hello: Hello, membership: Option<Membership>) -> Self {
         Self {
             supported_protocols: vec![VPN_PROTOCOL],
+            hello,
+            membership,
         }
     }
 
-    fn handshake(self, socket: &Stream) -> Result<(), Error> {
+    /// Exchange and validate handshake-messages. Both sides write first, so neither waits on
+    /// the other.
+    ///
+    /// The stream is authenticated by the connection's security-upgrade, so the received
+    /// WireGuard key was sent by the remote's PeerId. The key is derived from the remote's
+    /// secret, not its public identity, and can not be checked against the PeerId. In a private
+    /// network the remote also proves it holds the pre-shared key, which admits it to the
+    /// members-only behaviours.
+    async fn handshake(self, mut socket: Stream) -> Result<(Stream, Hello), Error> {
         trace!("{:?}", socket);
-        Ok(())
+
+        let message = serde_json::to_vec(&self.hello).map_err(Error::Malformed)?;
+        protocol::send_packet(&mut socket, &message).await?;
+
+        let message = protocol::recv_packet(&mut socket).await?;
+        let remote: Hello = serde_json::from_slice(&message).map_err(Error::Malformed)?;
+        trace!("Remote handshake, {:?}", remote);
+
+        if let Some(membership) = &self.membership {
+            membership.verify(&remote)?;
+        }
+        self.hello.validate(&remote)?;
+
+        Ok((socket, remote))
     }
 }
 
@@ -41,28 +132,25 @@ impl UpgradeInfo for Upgrade {
 }
 
 impl InboundUpgrade<Stream> for Upgrade {
-    type Output = (Stream, StreamProtocol);
+    type Output = (Stream, Hello);
 
     type Error = Error;
 
-    type Future = Ready<Result<Self::Output, Self::Error>>;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
     fn upgrade_inbound(self, socket: Stream, info: Self::Info) -> Self::Future {
         trace!("InboundUpgrade::upgrade_inbound, {:?}, {:?}", socket, info);
 
-        match self.handshake(&socket) {
-            Ok(()) => ready(Ok((socket, info))),
-            _ => ready(Err(Error::HandshakeError {})),
-        }
+        self.handshake(socket).boxed()
     }
 }
 
 impl OutboundUpgrade<Stream> for Upgrade {
-    type Output = (Stream, StreamProtocol);
+    type Output = (Stream, Hello);
 
     type Error = Error;
 
-    type Future = Ready<Result<Self::Output, Self::Error>>;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
     fn upgrade_outbound(self, socket: Stream, info: Self::Info) -> Self::Future {
         trace!(
@@ -70,9 +158,6 @@ impl OutboundUpgrade<Stream> for Upgrade {
             socket, info
         );
 
-        match self.handshake(&socket) {
-            Ok(()) => ready(Ok((socket, info))),
-            _ => ready(Err(Error::HandshakeError {})),
-        }
+        self.handshake(socket).boxed()
     }
 }
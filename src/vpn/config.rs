@@ -1,2 +1,103 @@
-#[derive(Debug, Default)]
-pub struct Config {}
+use boringtun::x25519::{PublicKey, StaticSecret};
+use ipnet::IpNet;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use super::behaviour::Hello;
+use crate::metrics::Metrics;
+use crate::network::NetworkKey;
+
+#[derive(Clone)]
+pub struct Config {
+    pub local_peer_id: PeerId,
+    /// The WireGuard static key, derived from the libp2p identity.
+    pub static_secret: StaticSecret,
+    /// Interval in seconds for WireGuard keepalives, `None` disables them.
+    pub persistent_keepalive: Option<u16>,
+    /// The local overlay IP-addresses.
+    pub addresses: Vec<IpAddr>,
+    /// Prefix the addresses were derived from, `None` if configured. Derived addresses yield
+    /// on conflicts.
+    pub address_prefix: Option<IpNet>,
+    /// Prefix of the overlay-network, unrouted destinations outside it are dropped instead of
+    /// resolved. `None` resolves any destination.
+    pub network_prefix: Option<IpNet>,
+    /// The MTU of the local interface.
+    pub mtu: u16,
+    /// Subnets advertised as reachable through this node.
+    pub routes: Vec<IpNet>,
+    /// Peers dialed on start and redialed whenever they drop.
+    pub peers: HashMap<PeerId, Peer>,
+    /// The key of the private network, remotes have to prove they hold it.
+    pub network: Option<NetworkKey>,
+    /// Counts the traffic, the dropped packets and the tunnels.
+    pub metrics: Metrics,
+}
+
+/// A statically configured peer
+#[derive(Debug, Clone, Default)]
+pub struct Peer {
+    pub addresses: Vec<Multiaddr>,
+    /// Source-addresses accepted from the peer, if empty the addresses and routes of its
+    /// handshake are accepted.
+    pub allowed_ips: Vec<IpNet>,
+    /// Overrides [`Config::persistent_keepalive`] for this peer.
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl Config {
+    pub fn new(local_peer_id: PeerId, static_secret: StaticSecret) -> Self {
+        Self {
+            local_peer_id,
+            static_secret,
+            persistent_keepalive: None,
+            addresses: vec![],
+            address_prefix: None,
+            network_prefix: None,
+            mtu: 1420,
+            routes: vec![],
+            peers: HashMap::new(),
+            network: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.static_secret)
+    }
+
+    /// The handshake-message announcing this node to `remote`.
+    pub fn hello(&self, remote: &PeerId) -> Hello {
+        let mut hello = Hello::new(
+            *self.public_key().as_bytes(),
+            self.addresses.clone(),
+            self.mtu,
+            self.routes.clone(),
+        );
+        hello.derived_addresses = self.address_prefix.is_some();
+        hello.membership_proof = self
+            .network
+            .as_ref()
+            .map(|network| network.membership_proof(&self.local_peer_id, remote));
+        hello
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("local_peer_id", &self.local_peer_id)
+            .field("public_key", &self.public_key())
+            .field("persistent_keepalive", &self.persistent_keepalive)
+            .field("addresses", &self.addresses)
+            .field("address_prefix", &self.address_prefix)
+            .field("network_prefix", &self.network_prefix)
+            .field("mtu", &self.mtu)
+            .field("routes", &self.routes)
+            .field("peers", &self.peers)
+            .field("network", &self.network)
+            .finish()
+    }
+}
@@ -1,3 +1,7 @@
+pub mod address;
 pub mod behaviour;
 pub mod config;
-// pub mod transport;
+pub mod packet;
+pub mod routing;
+pub mod transport;
+pub mod wireguard;
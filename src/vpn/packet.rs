@@ -0,0 +1,166 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Source-address of an IPv4- or IPv6-packet.
+pub fn source(packet: &[u8]) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 => ipv4(packet, 12),
+        6 => ipv6(packet, 8),
+        _ => None,
+    }
+}
+
+/// Destination-address of an IPv4- or IPv6-packet.
+pub fn destination(packet: &[u8]) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 => ipv4(packet, 16),
+        6 => ipv6(packet, 24),
+        _ => None,
+    }
+}
+
+/// Whether `address` names a single host beyond the link, packets to any other are not
+/// tunneled.
+pub fn is_unicast(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            !(address.is_unspecified()
+                || address.is_multicast()
+                || address.is_broadcast()
+                || address.is_link_local())
+        }
+        IpAddr::V6(address) => {
+            !(address.is_unspecified() || address.is_multicast() || address.is_unicast_link_local())
+        }
+    }
+}
+
+fn ipv4(packet: &[u8], offset: usize) -> Option<IpAddr> {
+    let octets: [u8; 4] = packet.get(offset..offset + 4)?.try_into().ok()?;
+    Some(IpAddr::V4(Ipv4Addr::from(octets)))
+}
+
+fn ipv6(packet: &[u8], offset: usize) -> Option<IpAddr> {
+    let octets: [u8; 16] = packet.get(offset..offset + 16)?.try_into().ok()?;
+    Some(IpAddr::V6(Ipv6Addr::from(octets)))
+}
+
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// An ICMP echo-request from `source` to `destination`, `None` if their IP-versions differ.
+pub fn echo_request(
+    source: IpAddr,
+    destination: IpAddr,
+    identifier: u16,
+    sequence: u16,
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    let mut icmp = vec![0; 8];
+    icmp[4..6].copy_from_slice(&identifier.to_be_bytes());
+    icmp[6..8].copy_from_slice(&sequence.to_be_bytes());
+    icmp.extend_from_slice(payload);
+
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            icmp[0] = ECHO_REQUEST;
+            let sum = checksum(&[&icmp]);
+            icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+            let mut packet = vec![0x45, 0];
+            packet.extend_from_slice(&(20 + icmp.len() as u16).to_be_bytes());
+            // Identification, don't fragment, TTL and protocol.
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, ICMP, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            let sum = checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+            packet.extend_from_slice(&icmp);
+            Some(packet)
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            icmp[0] = ECHO_REQUEST_V6;
+            let length = (icmp.len() as u32).to_be_bytes();
+            let sum = checksum(&[
+                &source.octets(),
+                &destination.octets(),
+                &length,
+                &[0, 0, 0, ICMPV6],
+                &icmp,
+            ]);
+            icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+            let mut packet = vec![0x60, 0, 0, 0];
+            packet.extend_from_slice(&length[2..]);
+            packet.extend_from_slice(&[ICMPV6, 64]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+
+            packet.extend_from_slice(&icmp);
+            Some(packet)
+        }
+        _ => None,
+    }
+}
+
+/// Identifier and sequence-number of an ICMP echo-reply.
+pub fn echo_reply(packet: &[u8]) -> Option<(u16, u16)> {
+    let (icmp, reply) = match packet.first()? >> 4 {
+        4 if *packet.get(9)? == ICMP => {
+            (packet.get(usize::from(packet[0] & 0x0f) * 4..)?, ECHO_REPLY)
+        }
+        6 if *packet.get(6)? == ICMPV6 => (packet.get(40..)?, ECHO_REPLY_V6),
+        _ => return None,
+    };
+
+    match *icmp.first()? == reply {
+        true => Some((
+            u16::from_be_bytes(icmp.get(4..6)?.try_into().ok()?),
+            u16::from_be_bytes(icmp.get(6..8)?.try_into().ok()?),
+        )),
+        false => None,
+    }
+}
+
+/// The internet-checksum over the concatenated `parts`, each of even length but the last.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for word in part.chunks(2) {
+            let high = u32::from(word[0]) << 8;
+            sum += high | u32::from(*word.get(1).unwrap_or(&0));
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unicast_is_tunneled() {
+        for address in ["10.1.2.3", "fd00::1", "2001:db8::1"] {
+            assert!(is_unicast(address.parse().unwrap()), "{address}");
+        }
+        for address in [
+            "0.0.0.0",
+            "224.0.0.251",
+            "255.255.255.255",
+            "169.254.1.1",
+            "::",
+            "ff02::1",
+            "fe80::1",
+        ] {
+            assert!(!is_unicast(address.parse().unwrap()), "{address}");
+        }
+    }
+}
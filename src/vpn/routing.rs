@@ -0,0 +1,138 @@
+use ipnet::IpNet;
+use libp2p::PeerId;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use tracing::warn;
+
+/// Maps overlay-addresses and advertised subnets to the peers owning them, looked up by
+/// longest-prefix match.
+#[derive(Debug, Default, Clone)]
+pub struct RoutingTable {
+    /// The routes of each peer, ordered so conflicts are resolved the same on every rebuild.
+    peers: BTreeMap<PeerId, Vec<IpNet>>,
+    /// Index over all routes, rebuilt whenever a peer's routes change.
+    routes: HashMap<IpNet, PeerId>,
+}
+
+impl RoutingTable {
+    /// Replace the routes of `peer_id`.
+    pub fn set_peer(&mut self, peer_id: PeerId, routes: Vec<IpNet>) {
+        let routes: Vec<IpNet> = routes.iter().map(IpNet::trunc).collect();
+        if self.peers.get(&peer_id) == Some(&routes) {
+            return;
+        }
+
+        self.peers.insert(peer_id, routes);
+        self.rebuild();
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        if self.peers.remove(peer_id).is_some() {
+            self.rebuild();
+        }
+    }
+
+    /// The peer owning the most specific route to `address`.
+    pub fn lookup(&self, address: IpAddr) -> Option<PeerId> {
+        let max_prefix_len = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        (0..=max_prefix_len).rev().find_map(|prefix_len| {
+            let net = IpNet::new(address, prefix_len).ok()?.trunc();
+            self.routes.get(&net).copied()
+        })
+    }
+
+    /// All routes, sorted by subnet.
+    pub fn routes(&self) -> Vec<(IpNet, PeerId)> {
+        let mut routes: Vec<(IpNet, PeerId)> = self
+            .routes
+            .iter()
+            .map(|(net, peer_id)| (*net, *peer_id))
+            .collect();
+        routes.sort();
+        routes
+    }
+
+    fn rebuild(&mut self) {
+        self.routes.clear();
+        for (peer_id, routes) in self.peers.iter() {
+            for net in routes {
+                match self.routes.get(net) {
+                    Some(owner) if owner != peer_id => {
+                        warn!("Route {} of {} is already owned by {}", net, peer_id, owner)
+                    }
+                    _ => {
+                        self.routes.insert(*net, *peer_id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::peer_id;
+
+    fn net(net: &str) -> IpNet {
+        net.parse().unwrap()
+    }
+
+    fn address(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn most_specific_route_wins() {
+        let (subnet, host) = (peer_id(), peer_id());
+        let mut table = RoutingTable::default();
+        table.set_peer(subnet, vec![net("10.1.0.0/16")]);
+        table.set_peer(host, vec![net("10.1.2.3/32")]);
+
+        assert_eq!(table.lookup(address("10.1.2.3")), Some(host));
+        assert_eq!(table.lookup(address("10.1.2.4")), Some(subnet));
+        assert_eq!(table.lookup(address("10.2.0.1")), None);
+        assert_eq!(table.lookup(address("fd00::1")), None);
+    }
+
+    #[test]
+    fn host_bits_are_ignored() {
+        let peer = peer_id();
+        let mut table = RoutingTable::default();
+        table.set_peer(peer, vec![net("fd00:1::5/64")]);
+
+        assert_eq!(table.lookup(address("fd00:1::9")), Some(peer));
+        assert_eq!(table.routes(), vec![(net("fd00:1::/64"), peer)]);
+    }
+
+    #[test]
+    fn removed_peer_is_not_routed() {
+        let peer = peer_id();
+        let mut table = RoutingTable::default();
+        table.set_peer(peer, vec![net("10.1.0.0/16")]);
+        table.remove_peer(&peer);
+
+        assert_eq!(table.lookup(address("10.1.2.3")), None);
+        assert!(table.routes().is_empty());
+    }
+
+    #[test]
+    fn conflict_is_resolved_independent_of_order() {
+        let (first, second) = (peer_id(), peer_id());
+        let owner = first.min(second);
+
+        let mut table = RoutingTable::default();
+        table.set_peer(first, vec![net("10.1.0.0/16")]);
+        table.set_peer(second, vec![net("10.1.0.0/16")]);
+        assert_eq!(table.lookup(address("10.1.2.3")), Some(owner));
+
+        let mut table = RoutingTable::default();
+        table.set_peer(second, vec![net("10.1.0.0/16")]);
+        table.set_peer(first, vec![net("10.1.0.0/16")]);
+        assert_eq!(table.lookup(address("10.1.2.3")), Some(owner));
+    }
+}
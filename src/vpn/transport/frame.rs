@@ -0,0 +1,299 @@
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, aead::AeadInPlace};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Size of the length-prefix of each frame.
+pub(crate) const HEADER_SIZE: usize = 2;
+
+/// Size of the Poly1305 authentication-tag.
+const TAG_SIZE: usize = 16;
+
+/// Largest payload fitting a frame: the length-prefix covers type, payload and tag.
+pub(crate) const MAX_PAYLOAD_SIZE: usize = u16::MAX as usize - 1 - TAG_SIZE;
+
+/// Frame carrying application-data.
+const FRAME_DATA: u8 = 0;
+/// Frame announcing that the sender switches to the next key, carries no payload.
+const FRAME_REKEY: u8 = 1;
+
+const KEY_INFO: &[u8] = b"vpnr transport 1 key";
+const REKEY_INFO: &[u8] = b"vpnr transport 1 rekey";
+
+/// Limits after which the sender switches to a new key.
+#[derive(Debug, Clone, Copy)]
+pub struct RekeyLimits {
+    pub bytes: u64,
+    pub interval: Duration,
+}
+
+impl Default for RekeyLimits {
+    fn default() -> Self {
+        Self {
+            bytes: 1 << 30,
+            interval: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Derive the keys of both directions from the pre-shared key and both nonces, the first
+/// key is used for sending.
+pub(crate) fn derive_keys(
+    psk: &[u8; 32],
+    local_nonce: &[u8],
+    remote_nonce: &[u8],
+) -> ([u8; 32], [u8; 32]) {
+    // Both sides have to agree on the salt, so the nonces are ordered.
+    let mut salt = [local_nonce, remote_nonce];
+    salt.sort();
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt.concat()), psk);
+
+    let expand = |nonce: &[u8]| {
+        let mut key = [0u8; 32];
+        hkdf.expand_multi_info(&[KEY_INFO, nonce], &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        key
+    };
+
+    (expand(local_nonce), expand(remote_nonce))
+}
+
+fn next_key(key: &[u8; 32]) -> [u8; 32] {
+    let mut next = [0u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(REKEY_INFO, &mut next)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    next
+}
+
+/// The nonce is the frame-counter, it is never sent. Replayed, dropped or reordered frames
+/// fail authentication.
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Encrypts frames for one direction of a connection.
+pub(crate) struct Sealer {
+    key: [u8; 32],
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+    bytes: u64,
+    since: Instant,
+    limits: RekeyLimits,
+}
+
+impl Sealer {
+    pub(crate) fn new(key: [u8; 32], limits: RekeyLimits) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(&key.into()),
+            key,
+            counter: 0,
+            bytes: 0,
+            since: Instant::now(),
+            limits,
+        }
+    }
+
+    /// Append a data-frame holding `payload` to `out`, preceded by a rekey-frame once a
+    /// limit is reached.
+    pub(crate) fn seal(&mut self, payload: &[u8], out: &mut Vec<u8>) {
+        assert!(payload.len() <= MAX_PAYLOAD_SIZE, "payload exceeds frame");
+
+        if self.bytes >= self.limits.bytes || self.since.elapsed() >= self.limits.interval {
+            self.seal_frame(FRAME_REKEY, &[], out);
+            self.key = next_key(&self.key);
+            self.cipher = ChaCha20Poly1305::new(&self.key.into());
+            self.counter = 0;
+            self.bytes = 0;
+            self.since = Instant::now();
+        }
+
+        self.seal_frame(FRAME_DATA, payload, out);
+        self.bytes += payload.len() as u64;
+    }
+
+    fn seal_frame(&mut self, frame_type: u8, payload: &[u8], out: &mut Vec<u8>) {
+        let length = (1 + payload.len() + TAG_SIZE) as u16;
+        let header = length.to_be_bytes();
+
+        let start = out.len();
+        out.extend_from_slice(&header);
+        out.push(frame_type);
+        out.extend_from_slice(payload);
+
+        let mut body = out.split_off(start + HEADER_SIZE);
+        self.cipher
+            .encrypt_in_place(&nonce(self.counter), &header, &mut body)
+            .expect("frame fits ChaCha20-Poly1305 limits");
+        out.append(&mut body);
+
+        self.counter += 1;
+    }
+}
+
+/// Decrypts frames of one direction of a connection.
+pub(crate) struct Opener {
+    key: [u8; 32],
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Opener {
+    pub(crate) fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(&key.into()),
+            key,
+            counter: 0,
+        }
+    }
+
+    /// Open the frame at the start of `buf`, returning its length and payload, or `None`
+    /// while the frame is incomplete. Rekey-frames are consumed with an empty payload.
+    pub(crate) fn open(&mut self, buf: &[u8]) -> io::Result<Option<(usize, Vec<u8>)>> {
+        if buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let header = [buf[0], buf[1]];
+        let length = u16::from_be_bytes(header) as usize;
+        if length < 1 + TAG_SIZE {
+            return Err(invalid_data("Frame shorter than its authentication-tag"));
+        }
+        if buf.len() < HEADER_SIZE + length {
+            return Ok(None);
+        }
+
+        let mut body = buf[HEADER_SIZE..HEADER_SIZE + length].to_vec();
+        self.cipher
+            .decrypt_in_place(&nonce(self.counter), &header, &mut body)
+            .map_err(|_| invalid_data("Frame failed authentication"))?;
+        self.counter += 1;
+
+        let payload = body.split_off(1);
+        match body[0] {
+            FRAME_DATA => Ok(Some((HEADER_SIZE + length, payload))),
+            FRAME_REKEY => {
+                self.key = next_key(&self.key);
+                self.cipher = ChaCha20Poly1305::new(&self.key.into());
+                self.counter = 0;
+                Ok(Some((HEADER_SIZE + length, vec![])))
+            }
+            _ => Err(invalid_data("Unknown frame-type")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(limits: RekeyLimits) -> (Sealer, Opener) {
+        let (send, _) = derive_keys(&[7; 32], &[1; 32], &[2; 32]);
+        let (_, recv) = derive_keys(&[7; 32], &[2; 32], &[1; 32]);
+        (Sealer::new(send, limits), Opener::new(recv))
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (mut sealer, mut opener) = pair(RekeyLimits::default());
+        let mut wire = vec![];
+        sealer.seal(b"hello", &mut wire);
+        sealer.seal(b"world", &mut wire);
+
+        let (length, payload) = opener.open(&wire).unwrap().unwrap();
+        assert_eq!(payload, b"hello");
+        let (_, payload) = opener.open(&wire[length..]).unwrap().unwrap();
+        assert_eq!(payload, b"world");
+    }
+
+    #[test]
+    fn tampered_frame_is_rejected() {
+        let (mut sealer, mut opener) = pair(RekeyLimits::default());
+        let mut wire = vec![];
+        sealer.seal(b"hello", &mut wire);
+
+        wire[HEADER_SIZE + 2] ^= 0x01;
+        assert!(opener.open(&wire).is_err());
+    }
+
+    #[test]
+    fn tampered_length_is_rejected() {
+        let (mut sealer, mut opener) = pair(RekeyLimits::default());
+        let mut wire = vec![];
+        sealer.seal(b"hello", &mut wire);
+
+        wire[1] -= 1;
+        assert!(opener.open(&wire).is_err());
+    }
+
+    #[test]
+    fn truncated_frame_is_incomplete() {
+        let (mut sealer, mut opener) = pair(RekeyLimits::default());
+        let mut wire = vec![];
+        sealer.seal(b"hello", &mut wire);
+
+        for length in 0..wire.len() {
+            assert!(opener.open(&wire[..length]).unwrap().is_none());
+        }
+        assert!(opener.open(&wire).unwrap().is_some());
+    }
+
+    #[test]
+    fn replayed_frame_is_rejected() {
+        let (mut sealer, mut opener) = pair(RekeyLimits::default());
+        let mut wire = vec![];
+        sealer.seal(b"hello", &mut wire);
+
+        assert!(opener.open(&wire).unwrap().is_some());
+        assert!(opener.open(&wire).is_err());
+    }
+
+    #[test]
+    fn reordered_frames_are_rejected() {
+        let (mut sealer, mut opener) = pair(RekeyLimits::default());
+        let mut first = vec![];
+        let mut second = vec![];
+        sealer.seal(b"hello", &mut first);
+        sealer.seal(b"world", &mut second);
+
+        assert!(opener.open(&second).is_err());
+    }
+
+    #[test]
+    fn rekeys_after_byte_limit() {
+        let (mut sealer, mut opener) = pair(RekeyLimits {
+            bytes: 8,
+            ..RekeyLimits::default()
+        });
+        let mut wire = vec![];
+        sealer.seal(b"0123456789", &mut wire);
+        let first = wire.len();
+        sealer.seal(b"abc", &mut wire);
+
+        let (length, _) = opener.open(&wire).unwrap().unwrap();
+        assert_eq!(length, first);
+        let (length, payload) = opener.open(&wire[first..]).unwrap().unwrap();
+        assert!(payload.is_empty());
+        let (_, payload) = opener.open(&wire[first + length..]).unwrap().unwrap();
+        assert_eq!(payload, b"abc");
+
+        // Frames under the previous key are not accepted anymore.
+        assert!(opener.open(&wire[..first]).is_err());
+    }
+
+    #[test]
+    fn directions_use_distinct_keys() {
+        let (send, recv) = derive_keys(&[7; 32], &[1; 32], &[2; 32]);
+        assert_ne!(send, recv);
+
+        let (other, _) = derive_keys(&[8; 32], &[1; 32], &[2; 32]);
+        assert_ne!(send, other);
+    }
+}
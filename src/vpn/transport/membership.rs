@@ -0,0 +1,90 @@
+use futures::{future, prelude::*};
+use libp2p::{
+    PeerId,
+    core::{
+        ConnectedPoint, Endpoint,
+        muxing::{StreamMuxer, StreamMuxerExt},
+    },
+};
+use std::time::Duration;
+use std::{error, io};
+
+use super::Error;
+use crate::network::NetworkKey;
+
+/// Time the remote has to prove its membership.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Membership-check for connections the transport can not wrap, QUIC's: both sides prove
+/// they hold the pre-shared key on the first substream, before the connection is handed to
+/// the swarm.
+#[derive(Clone)]
+pub struct Membership {
+    network: NetworkKey,
+    local: PeerId,
+}
+
+impl Membership {
+    pub fn new(network: NetworkKey, local: PeerId) -> Self {
+        Self { network, local }
+    }
+
+    /// Exchange proofs with `remote` on a substream of `muxer`, the dialer opens it and proves
+    /// its membership first.
+    pub async fn verify<M>(
+        self,
+        (remote, mut muxer): (PeerId, M),
+        endpoint: ConnectedPoint,
+    ) -> Result<(PeerId, M), Error>
+    where
+        M: StreamMuxer + Unpin,
+        M::Substream: AsyncRead + AsyncWrite + Unpin,
+        M::Error: error::Error + Send + Sync + 'static,
+    {
+        // Of a hole-punched connection both sides dial, the role decides who opens.
+        let dialer = matches!(
+            endpoint,
+            ConnectedPoint::Dialer {
+                role_override: Endpoint::Dialer,
+                ..
+            }
+        );
+
+        let exchange = async {
+            let mut stream = match dialer {
+                true => future::poll_fn(|cx| muxer.poll_outbound_unpin(cx)).await,
+                false => future::poll_fn(|cx| muxer.poll_inbound_unpin(cx)).await,
+            }
+            .map_err(io::Error::other)?;
+
+            let proof = self.network.membership_proof(&self.local, &remote);
+            let mut remote_proof = [0u8; 32];
+            if dialer {
+                stream.write_all(&proof).await?;
+                stream.flush().await?;
+            }
+            stream.read_exact(&mut remote_proof).await?;
+
+            // A non-member learns nothing, the listener answers only after verifying.
+            if !self
+                .network
+                .verify_membership(&remote_proof, &remote, &self.local)
+            {
+                return Ok(false);
+            }
+            if !dialer {
+                stream.write_all(&proof).await?;
+            }
+            stream.close().await?;
+            Ok::<_, io::Error>(true)
+        };
+
+        let outcome = tokio::time::timeout(TIMEOUT, exchange).await;
+        match outcome {
+            Ok(Ok(true)) => Ok((remote, muxer)),
+            Ok(Ok(false)) => Err(Error::KeyMismatch),
+            Ok(Err(e)) => Err(Error::HandshakeError(e)),
+            Err(_) => Err(Error::HandshakeError(io::ErrorKind::TimedOut.into())),
+        }
+    }
+}
@@ -1,13 +1,11 @@
-mod crypt_writer;
+mod frame;
+mod membership;
 
-use crypt_writer::CryptWriter;
+pub use frame::RekeyLimits;
+pub use membership::Membership;
+
+use frame::{HEADER_SIZE, MAX_PAYLOAD_SIZE, Opener, Sealer};
 use futures::prelude::*;
-use pin_project::pin_project;
-use salsa20::{
-    Salsa20, XSalsa20,
-    cipher::{KeyIvInit, StreamCipher},
-};
-use sha3::Shake128;
 use std::error;
 use std::io;
 use std::{
@@ -17,55 +15,34 @@ use std::{
 };
 
 const NONCE_SIZE: usize = 32;
-const WRITE_BUFFER_SIZE: usize = 1024;
-
-#[derive(Copy, Clone, PartialEq, Eq)]
-pub struct PreSharedKey([u8; KEY_SIZE]);
-
-impl PreSharedKey {
-    /// Create a new pre shared key from raw bytes
-    pub fn new(data: [u8; KEY_SIZE]) -> Self {
-        Self(data)
-    }
-
-    /// Compute PreSharedKey fingerprint identical to the go-libp2p fingerprint.
-    /// The computation of the fingerprint is not specified in the spec.
-    ///
-    /// This provides a way to check that private keys are properly configured
-    /// without dumping the key itself to the console.
-    pub fn fingerprint(&self) -> Fingerprint {
-        use std::io::{Read, Write};
-        let mut enc = [0u8; 64];
-        let nonce: [u8; 8] = *b"finprint";
-        let mut out = [0u8; 16];
-        let mut cipher = Salsa20::new(&Into::into(self.0), &nonce.into());
-        cipher.apply_keystream(&mut enc);
-        let mut hasher = Shake128::default();
-        hasher.write_all(&enc).expect("shake128 failed");
-        hasher
-            .finalize_xof()
-            .read_exact(&mut out)
-            .expect("shake128 failed");
-        Fingerprint(out)
-    }
-}
+const READ_BUFFER_SIZE: usize = 4096;
 
-/// Private network configuration
-#[derive(Debug, Clone)]
+/// Private network transport-upgrade: traffic is encrypted and authenticated with
+/// ChaCha20-Poly1305, keyed from the pre-shared key and both sides' nonces.
+#[derive(Clone)]
 pub struct Transport {
-    /// the PreSharedKey to use for encryption
-    psk: PreSharedKey,
+    /// Secret derived from the pre-shared key
+    key: [u8; 32],
+    limits: RekeyLimits,
 }
 
 impl Transport {
-    pub fn new(psk: PreSharedKey) -> Self {
-        Self { psk }
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            limits: RekeyLimits::default(),
+        }
+    }
+
+    pub fn with_rekey_limits(mut self, limits: RekeyLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// upgrade a connection to use pre shared key encryption.
     ///
-    /// the upgrade works by both sides exchanging 24 byte nonces and then encrypting
-    /// subsequent traffic with XSalsa20
+    /// Both sides exchange 32 byte nonces, derive a key per direction and prove they hold
+    /// the same pre-shared key with an empty frame before any data is passed on.
     pub async fn handshake<TSocket>(self, mut socket: TSocket) -> Result<Output<TSocket>, Error>
     where
         TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
@@ -78,104 +55,257 @@ impl Transport {
             .write_all(&local_nonce)
             .await
             .map_err(Error::HandshakeError)?;
-        socket.flush().await?;
+        socket.flush().await.map_err(Error::HandshakeError)?;
         socket
             .read_exact(&mut remote_nonce)
             .await
             .map_err(Error::HandshakeError)?;
+
+        // A remote echoing our nonce would get our keys for both directions.
+        if local_nonce == remote_nonce {
+            return Err(Error::ReflectedNonce);
+        }
+
         tracing::trace!("setting up ciphers");
-        let write_cipher = XSalsa20::new(&self.psk.0.into(), &local_nonce);
-        let read_cipher = XSalsa20::new(&self.psk.0.into(), &remote_nonce);
-        Ok(Output::new(socket, write_cipher, read_cipher))
+        let (send_key, recv_key) = frame::derive_keys(&self.key, &local_nonce, &remote_nonce);
+        let mut output = Output::new(
+            socket,
+            Sealer::new(send_key, self.limits),
+            Opener::new(recv_key),
+        );
+
+        output.sealer.seal(&[], &mut output.write_buf);
+        output.flush().await.map_err(Error::HandshakeError)?;
+
+        loop {
+            match output.opener.open(&output.read_buf) {
+                Ok(Some((length, payload))) if payload.is_empty() => {
+                    output.read_buf.drain(..length);
+                    return Ok(output);
+                }
+                Ok(None) => {}
+                _ => return Err(Error::KeyMismatch),
+            }
+
+            let mut chunk = [0u8; READ_BUFFER_SIZE];
+            let n = output
+                .inner
+                .read(&mut chunk)
+                .await
+                .map_err(Error::HandshakeError)?;
+            if n == 0 {
+                return Err(Error::HandshakeError(io::ErrorKind::UnexpectedEof.into()));
+            }
+            output.read_buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transport")
+            .field("limits", &self.limits)
+            .finish()
     }
 }
 
 /// The result of a handshake. This implements AsyncRead and AsyncWrite and can therefore
 /// be used as base for additional upgrades.
-#[pin_project]
 pub struct Output<S> {
-    #[pin]
-    inner: CryptWriter<S>,
-    read_cipher: XSalsa20,
+    inner: S,
+    sealer: Sealer,
+    opener: Opener,
+    /// Sealed frames not yet written to `inner`.
+    write_buf: Vec<u8>,
+    /// Received bytes not yet forming a complete frame.
+    read_buf: Vec<u8>,
+    /// Payload of the last opened frame, and how much of it was read.
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
 }
 
-impl<S: AsyncRead + AsyncWrite> Output<S> {
-    fn new(inner: S, write_cipher: XSalsa20, read_cipher: XSalsa20) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> Output<S> {
+    fn new(inner: S, sealer: Sealer, opener: Opener) -> Self {
         Self {
-            inner: CryptWriter::with_capacity(WRITE_BUFFER_SIZE, inner, write_cipher),
-            read_cipher,
+            inner,
+            sealer,
+            opener,
+            write_buf: vec![],
+            read_buf: vec![],
+            plaintext: vec![],
+            plaintext_pos: 0,
+        }
+    }
+
+    /// Write the sealed frames to the inner stream.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n);
         }
+        Poll::Ready(Ok(()))
     }
 }
 
-impl<S: AsyncRead + AsyncWrite> AsyncRead for Output<S> {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Output<S> {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let this = self.project();
-        let result = this.inner.get_pin_mut().poll_read(cx, buf);
-        if let Poll::Ready(Ok(size)) = &result {
-            tracing::trace!(bytes=%size, "read bytes");
-            this.read_cipher.apply_keystream(&mut buf[..*size]);
-            tracing::trace!(bytes=%size, "decrypted bytes");
+        let this = self.get_mut();
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let n = buf.len().min(this.plaintext.len() - this.plaintext_pos);
+                buf[..n].copy_from_slice(&this.plaintext[this.plaintext_pos..][..n]);
+                this.plaintext_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+
+            if let Some((length, payload)) = this.opener.open(&this.read_buf)? {
+                this.read_buf.drain(..length);
+                this.plaintext = payload;
+                this.plaintext_pos = 0;
+                continue;
+            }
+
+            let mut chunk = [0u8; READ_BUFFER_SIZE];
+            let n = futures::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if n == 0 {
+                if this.read_buf.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Stream ended within a frame",
+                )));
+            }
+            tracing::trace!(bytes=%n, "read bytes");
+            this.read_buf.extend_from_slice(&chunk[..n]);
         }
-        result
     }
 }
 
-impl<S: AsyncRead + AsyncWrite> AsyncWrite for Output<S> {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Output<S> {
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        self.project().inner.poll_write(cx, buf)
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let this = self.get_mut();
+        // Apply backpressure once a frame is waiting.
+        if this.write_buf.len() >= HEADER_SIZE + MAX_PAYLOAD_SIZE {
+            futures::ready!(this.poll_write_buf(cx))?;
+        }
+
+        let n = buf.len().min(MAX_PAYLOAD_SIZE);
+        this.sealer.seal(&buf[..n], &mut this.write_buf);
+        tracing::trace!(bytes=%n, "encrypted bytes");
+        Poll::Ready(Ok(n))
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        self.project().inner.poll_flush(cx)
+        let this = self.get_mut();
+        futures::ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        self.project().inner.poll_close(cx)
+        let this = self.get_mut();
+        futures::ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
     }
 }
 
-type IoError = io::Error;
-
 /// Error when writing or reading private swarms
 #[derive(Debug)]
 pub enum Error {
     /// Error during handshake.
     HandshakeError(io::Error),
-    /// I/O error.
-    IoError(io::Error),
-}
-
-impl From<io::Error> for Error {
-    #[inline]
-    fn from(err: io::Error) -> Self {
-        Self::IoError(err)
-    }
+    /// The remote sent back our own nonce.
+    ReflectedNonce,
+    /// The remote holds a different pre-shared key.
+    KeyMismatch,
 }
 
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
-        match *self {
-            Error::HandshakeError(ref err) => Some(err),
-            Error::IoError(ref err) => Some(err),
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::HandshakeError(err) => Some(err),
+            _ => None,
         }
     }
 }
 
 impl fmt::Display for Error {
-    #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
             Error::HandshakeError(e) => write!(f, "Handshake error: {e}"),
-            Error::IoError(e) => write!(f, "I/O error: {e}"),
+            Error::ReflectedNonce => write!(f, "Remote reflected the local nonce"),
+            Error::KeyMismatch => write!(f, "Remote uses a different pre-shared key"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    /// An `Output` past the handshake, reading `wire`.
+    fn output(wire: Vec<u8>) -> Output<Cursor<Vec<u8>>> {
+        Output::new(
+            Cursor::new(wire),
+            Sealer::new([0; 32], RekeyLimits::default()),
+            Opener::new([1; 32]),
+        )
+    }
+
+    fn sealed(payloads: &[&[u8]]) -> Vec<u8> {
+        let mut sealer = Sealer::new([1; 32], RekeyLimits::default());
+        let mut wire = vec![];
+        for payload in payloads {
+            sealer.seal(payload, &mut wire);
         }
+        wire
+    }
+
+    #[test]
+    fn reads_frames() {
+        let mut output = output(sealed(&[b"hello ", b"world"]));
+
+        let mut received = vec![];
+        block_on(output.read_to_end(&mut received)).unwrap();
+        assert_eq!(received, b"hello world");
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        let mut wire = sealed(&[b"hello"]);
+        wire.pop();
+        let mut output = output(wire);
+
+        let error = block_on(output.read_to_end(&mut vec![])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn writes_frames() {
+        let mut output = output(vec![]);
+        block_on(output.write_all(b"hello")).unwrap();
+        block_on(output.flush()).unwrap();
+
+        let mut opener = Opener::new([0; 32]);
+        let wire = output.inner.into_inner();
+        let (_, payload) = opener.open(&wire).unwrap().unwrap();
+        assert_eq!(payload, b"hello");
     }
 }
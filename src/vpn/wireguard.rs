@@ -0,0 +1,105 @@
+use boringtun::noise::{Tunn, TunnResult, errors::WireGuardError};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use libp2p::identity::Keypair;
+use tracing::{trace, warn};
+
+/// Domain-separator for deriving the WireGuard static key from the libp2p identity.
+const STATIC_KEY_DOMAIN: &[u8] = b"vpnr wireguard static key";
+
+/// Largest datagram the tunnel reads or writes, sized for the frames of the VPN-stream.
+const BUFFER_SIZE: usize = u16::MAX as usize;
+
+/// Derive the WireGuard static key from the libp2p identity, binding both to the same secret.
+///
+/// Only ed25519 identities are supported.
+pub fn static_secret(keypair: &Keypair) -> Option<StaticSecret> {
+    keypair
+        .derive_secret(STATIC_KEY_DOMAIN)
+        .map(StaticSecret::from)
+}
+
+/// The datagrams and packets produced by one call into the tunnel.
+#[derive(Debug, Default)]
+pub struct Output {
+    /// Encrypted datagrams to send to the remote.
+    pub network: Vec<Vec<u8>>,
+    /// Decrypted IP-packets to hand to the local interface.
+    pub interface: Vec<Vec<u8>>,
+}
+
+/// A WireGuard session with a single remote, driven by the handler of its connection.
+pub struct Tunnel {
+    tunn: Tunn,
+    buffer: Vec<u8>,
+}
+
+impl Tunnel {
+    pub fn new(
+        static_secret: StaticSecret,
+        remote_public_key: PublicKey,
+        preshared_key: Option<[u8; 32]>,
+        persistent_keepalive: Option<u16>,
+    ) -> Self {
+        // boringtun uses the upper 24 bits of the session-index.
+        let index = rand::random::<u32>() >> 8;
+
+        Self {
+            tunn: Tunn::new(
+                static_secret,
+                remote_public_key,
+                preshared_key,
+                persistent_keepalive,
+                index,
+                None,
+            ),
+            buffer: vec![0u8; BUFFER_SIZE],
+        }
+    }
+
+    /// Encrypt an IP-packet read from the local interface.
+    ///
+    /// Without an active session the packet is queued and a handshake-initiation is returned.
+    pub fn encapsulate(&mut self, packet: &[u8]) -> Output {
+        let mut output = Output::default();
+        let result = self.tunn.encapsulate(packet, &mut self.buffer);
+        collect(result, &mut output);
+        output
+    }
+
+    /// Decrypt a datagram received from the remote.
+    pub fn decapsulate(&mut self, datagram: &[u8]) -> Output {
+        let mut output = Output::default();
+        let mut result = self.tunn.decapsulate(None, datagram, &mut self.buffer);
+
+        // After a completed handshake, boringtun releases queued packets one call at a time.
+        while let TunnResult::WriteToNetwork(_) = result {
+            collect(result, &mut output);
+            result = self.tunn.decapsulate(None, &[], &mut self.buffer);
+        }
+        collect(result, &mut output);
+
+        output
+    }
+
+    /// Drive handshakes, rekeying and keepalives, to be called periodically.
+    pub fn update_timers(&mut self) -> Output {
+        let mut output = Output::default();
+        let result = self.tunn.update_timers(&mut self.buffer);
+        collect(result, &mut output);
+        output
+    }
+}
+
+fn collect(result: TunnResult<'_>, output: &mut Output) {
+    match result {
+        TunnResult::Done => {}
+        TunnResult::WriteToNetwork(datagram) => output.network.push(datagram.to_vec()),
+        TunnResult::WriteToTunnelV4(packet, _) | TunnResult::WriteToTunnelV6(packet, _) => {
+            output.interface.push(packet.to_vec())
+        }
+        TunnResult::Err(WireGuardError::ConnectionExpired) => {
+            trace!("WireGuard session expired");
+        }
+        TunnResult::Err(e) => warn!("WireGuard error, {:?}", e),
+    }
+}